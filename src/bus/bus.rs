@@ -18,6 +18,12 @@ pub struct Bus {
     sender: broadcast::Sender<Event>,
 }
 
+impl Default for Bus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Bus {
     pub fn new() -> Self {
         let (tx, _) = broadcast::channel(32);
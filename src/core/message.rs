@@ -5,6 +5,10 @@ use serde::{Deserialize, Deserializer, Serialize};
 
 pub type RawData = Vec<u8>;
 
+/// Headers are the named values attached to a message, f.e. a trace ID or a content type.
+/// They keep the order, in which they were added, and a name could be repeated.
+pub type Headers = Vec<(String, RawData)>;
+
 #[derive(Debug, Clone)]
 pub struct Message {
     pub offset: usize,
@@ -13,10 +17,7 @@ pub struct Message {
 
     pub key: Option<RawData>,
     pub value: RawData,
-    // TODO: maybe add:
-    // - attributes(HashMap<String, String>)
-    // - headers(Vec<String>
-    // They are used in kafka, but idk how to use them here
+    pub headers: Headers,
 }
 
 impl Message {
@@ -25,6 +26,7 @@ impl Message {
         timestamp: DateTime<Utc>,
         key: Option<RawData>,
         value: RawData,
+        headers: Headers,
     ) -> Self {
         Self {
             offset,
@@ -32,6 +34,7 @@ impl Message {
             timestamp,
             key,
             value,
+            headers,
         }
     }
 }
@@ -42,9 +45,14 @@ impl fmt::Display for Message {
         write!(f, "[Size {}]", self.size)?;
         write!(f, "[Timestamp {}]", self.timestamp)?;
         match &self.key {
-            Some(v) => write!(f, "[Key {:?}", v),
+            Some(v) => write!(f, "[Key {:?}]", v),
             None => write!(f, "[Key NONE]"),
         }?;
+        write!(f, "[Headers")?;
+        for (name, value) in self.headers.iter() {
+            write!(f, " {}={:?}", name, value)?;
+        }
+        write!(f, "]")?;
         write!(f, "[Data {:?}]", self.value)
     }
 }
@@ -57,6 +65,7 @@ struct SerializedMessage {
 
     pub key: Option<RawData>,
     pub value: RawData,
+    pub headers: Headers,
 }
 
 impl From<Message> for SerializedMessage {
@@ -67,18 +76,45 @@ impl From<Message> for SerializedMessage {
             timestamp: value.timestamp.timestamp_nanos_opt().unwrap(),
             key: value.key,
             value: value.value,
+            headers: value.headers,
         }
     }
 }
 
-impl Into<Message> for SerializedMessage {
-    fn into(self) -> Message {
-        Message {
-            offset: self.offset,
-            size: self.size,
-            timestamp: Utc.timestamp_nanos(self.timestamp),
-            key: self.key,
-            value: self.value,
+impl From<SerializedMessage> for Message {
+    fn from(value: SerializedMessage) -> Self {
+        Self {
+            offset: value.offset,
+            size: value.size,
+            timestamp: Utc.timestamp_nanos(value.timestamp),
+            key: value.key,
+            value: value.value,
+            headers: value.headers,
+        }
+    }
+}
+
+/// LegacyMessage is the way a Message was serialized before the headers were added,
+/// it is used only to read the old data.
+#[derive(Deserialize)]
+pub struct LegacyMessage {
+    offset: usize,
+    size: usize,
+    timestamp: i64,
+
+    key: Option<RawData>,
+    value: RawData,
+}
+
+impl From<LegacyMessage> for Message {
+    fn from(value: LegacyMessage) -> Self {
+        Self {
+            offset: value.offset,
+            size: value.size,
+            timestamp: Utc.timestamp_nanos(value.timestamp),
+            key: value.key,
+            value: value.value,
+            headers: Headers::new(),
         }
     }
 }
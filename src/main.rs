@@ -1,7 +1,3 @@
-mod bus;
-mod core;
-mod storage;
-
 use std::{
     fs::File,
     io::Read,
@@ -10,20 +6,39 @@ use std::{
 
 use chrono::Utc;
 
+use depressed_mq::storage;
+
 #[tokio::main]
 async fn main() {
     println!("Hello, world!");
 
-    let mut p1 = storage::partition::Partition::new("./test".into(), 0, 5).unwrap();
+    let config = storage::partition::PartitionConfig {
+        max_segment_messages: 5,
+        ..Default::default()
+    };
+    let p1 = storage::partition::Partition::new("./test".into(), 0, config).unwrap();
     println!("{} is loaded", p1);
+    let report = p1.load_report();
+    for upgraded in report.upgraded.iter() {
+        println!("Upgraded segment: {}", upgraded);
+    }
+    for repaired in report.repaired.iter() {
+        println!("Repaired segment: {}", repaired);
+    }
+    let p1 = storage::async_partition::AsyncPartition::new(p1).unwrap();
 
     for _ in 0..10 {
-        p1.write(Utc::now(), None, generate_random_vec()).unwrap();
+        p1.append(
+            vec![(Utc::now(), None, generate_random_vec(), vec![])],
+            storage::async_partition::Durability::Synced,
+        )
+        .await
+        .unwrap();
     }
     println!("{}", p1);
 
     for i in 0..10 {
-        match p1.read(i) {
+        match p1.read(i).await {
             Ok(msg) => println!("{}", msg),
             Err(e) => println!("ERROR: {}", e),
         }
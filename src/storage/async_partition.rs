@@ -0,0 +1,210 @@
+use core::fmt;
+use std::{
+    io::{Error, ErrorKind},
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use chrono::{DateTime, Utc};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::core::message::{Headers, Message, RawData};
+
+use super::partition::Partition;
+
+/// APPEND_QUEUE_SIZE is the amount of appends that could wait for the writer task,
+/// after which AsyncPartition::append waits for a free place.
+const APPEND_QUEUE_SIZE: usize = 1024;
+
+/// MAX_APPENDS_PER_WRITE is the max amount of the queued appends written by the writer task
+/// before they are synced and completed together.
+const MAX_APPENDS_PER_WRITE: usize = 64;
+
+/// Batch is the messages of an append as (timestamp, key, value, headers).
+pub type Batch = Vec<(DateTime<Utc>, Option<RawData>, RawData, Headers)>;
+
+/// Durability is the level, after reaching which AsyncPartition::append completes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Durability {
+    /// The messages are written to the log and could be read, but could still be lost
+    /// on a crash unless the durability policy of the partition syncs them.
+    Written,
+    /// The messages are synced to the disk.
+    Synced,
+}
+
+/// AsyncPartition is a handle of a partition, which could be used from the async tasks
+/// without blocking the runtime.
+///
+/// The writes are done by a dedicated writer task, which takes the appends from a queue,
+/// writes all of the queued ones at once and syncs them together if any of them asks for it.
+/// The reads run on the blocking pool and share the partition with the writer task, so they
+/// run concurrently with each other and with the writes. The handle is cheap to clone
+/// and the writer task stops after all of the handles are dropped.
+#[derive(Clone)]
+pub struct AsyncPartition {
+    partition: Arc<Partition>,
+    appends: mpsc::Sender<Append>,
+    /// writer_error is the last failure of the writer task, which is not taken yet.
+    writer_error: Arc<Mutex<Option<Error>>>,
+}
+
+// Append is a request to the writer task.
+struct Append {
+    batch: Batch,
+    durability: Durability,
+    done: oneshot::Sender<Result<Range<usize>, Error>>,
+}
+
+impl AsyncPartition {
+    /// Moves the partition into the handle and spawns its writer task,
+    /// so it has to be called inside of a tokio runtime.
+    pub fn new(partition: Partition) -> Result<Self, Error> {
+        let runtime = tokio::runtime::Handle::try_current().map_err(|e| {
+            Error::other(format!("async partition requires a tokio runtime: {}", e))
+        })?;
+
+        let partition = Arc::new(partition);
+        let writer_error = Arc::new(Mutex::new(None));
+        let (appends, queue) = mpsc::channel(APPEND_QUEUE_SIZE);
+        runtime.spawn(Self::run_writer(
+            partition.clone(),
+            queue,
+            writer_error.clone(),
+        ));
+
+        Ok(Self {
+            partition,
+            appends,
+            writer_error,
+        })
+    }
+
+    /// Appends the messages to the log and returns the range of offsets given to them
+    /// after the requested durability level is reached.
+    ///
+    /// The appends are written in the order, in which they are called. If the future is
+    /// dropped before it completes, the messages could still be written.
+    pub async fn append(
+        &self,
+        batch: Batch,
+        durability: Durability,
+    ) -> Result<Range<usize>, Error> {
+        let (done, written) = oneshot::channel();
+        let append = Append {
+            batch,
+            durability,
+            done,
+        };
+
+        if self.appends.send(append).await.is_err() {
+            return Err(Self::writer_stopped());
+        }
+        written.await.map_err(|_| Self::writer_stopped())?
+    }
+
+    /// Returns the last failure of the writer task, if there was one since the last call.
+    ///
+    /// The appends, which were being written when the writer task failed, fail as well,
+    /// but some of their messages could be written.
+    pub fn take_writer_error(&self) -> Option<Error> {
+        self.writer_error.lock().unwrap().take()
+    }
+
+    pub async fn read(&self, offset: usize) -> Result<Message, Error> {
+        self.blocking_read(move |p| p.read(offset)).await
+    }
+
+    /// Same as Partition::read_range, but runs on the blocking pool.
+    pub async fn read_range(
+        &self,
+        start: usize,
+        max_messages: usize,
+        max_bytes: usize,
+    ) -> Result<Vec<Message>, Error> {
+        self.blocking_read(move |p| p.read_range(start, max_messages, max_bytes))
+            .await
+    }
+
+    /// Same as Partition::offset_for_timestamp, but runs on the blocking pool.
+    pub async fn offset_for_timestamp(
+        &self,
+        timestamp: DateTime<Utc>,
+    ) -> Result<Option<usize>, Error> {
+        self.blocking_read(move |p| p.offset_for_timestamp(timestamp))
+            .await
+    }
+
+    // blocking_read runs the read on the blocking pool.
+    async fn blocking_read<T, F>(&self, read: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Partition) -> Result<T, Error> + Send + 'static,
+    {
+        let partition = self.partition.clone();
+        tokio::task::spawn_blocking(move || read(&partition))
+            .await
+            .map_err(|e| Error::other(format!("partition read failed: {}", e)))?
+    }
+
+    // run_writer writes the queued appends until all of the handles are dropped.
+    async fn run_writer(
+        partition: Arc<Partition>,
+        mut queue: mpsc::Receiver<Append>,
+        writer_error: Arc<Mutex<Option<Error>>>,
+    ) {
+        let mut appends = Vec::with_capacity(MAX_APPENDS_PER_WRITE);
+        while queue.recv_many(&mut appends, MAX_APPENDS_PER_WRITE).await > 0 {
+            let partition = partition.clone();
+            let pending = std::mem::take(&mut appends);
+
+            match tokio::task::spawn_blocking(move || Self::write(&partition, pending)).await {
+                Ok(()) => continue,
+                // the appends are dropped along with the task, so their futures fail.
+                Err(e) => {
+                    let e = Error::other(format!("partition writer failed: {}", e));
+                    *writer_error.lock().unwrap() = Some(e);
+                }
+            }
+        }
+    }
+
+    // write writes the appends one after another and syncs the partition once,
+    // if any of the written ones asks for it, before completing them.
+    fn write(partition: &Partition, appends: Vec<Append>) {
+        let mut written = Vec::with_capacity(appends.len());
+        for append in appends {
+            let result = partition.write_batch(append.batch);
+            written.push((append.done, append.durability, result));
+        }
+
+        let sync_needed = written
+            .iter()
+            .any(|(_, durability, result)| *durability == Durability::Synced && result.is_ok());
+        let synced = match sync_needed {
+            true => partition.sync(),
+            false => Ok(()),
+        };
+
+        for (done, durability, result) in written {
+            let result = match (&synced, durability) {
+                (Err(e), Durability::Synced) if result.is_ok() => {
+                    Err(Error::new(e.kind(), format!("sync failed: {}", e)))
+                }
+                _ => result,
+            };
+            // the caller could have stopped waiting, the messages are written anyway.
+            let _ = done.send(result);
+        }
+    }
+
+    fn writer_stopped() -> Error {
+        Error::new(ErrorKind::BrokenPipe, "partition writer is stopped")
+    }
+}
+
+impl fmt::Display for AsyncPartition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.partition)
+    }
+}
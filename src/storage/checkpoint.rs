@@ -0,0 +1,48 @@
+use std::io::{Error, ErrorKind};
+
+use super::{format, vfs::Vfs};
+
+/// LOG_START_OFFSET_FILE is the file in a partition's directory, which stores the log start offset
+/// set by Partition::delete_records_before.
+pub const LOG_START_OFFSET_FILE: &str = "log-start-offset.checkpoint";
+
+/// Reads the log start offset from the checkpoint in the given partition's directory,
+/// or returns None if there is no checkpoint.
+pub fn read_log_start_offset(vfs: &dyn Vfs, path: &str) -> Result<Option<usize>, Error> {
+    let file_path = format!("{}/{}", path, LOG_START_OFFSET_FILE);
+    if !vfs.exists(&file_path) {
+        return Ok(None);
+    }
+
+    let file = vfs.open(&file_path)?;
+    let mut buffer = vec![0u8; file.size()? as usize];
+    file.read_exact_at(&mut buffer, 0)?;
+
+    format::decode_checkpoint(&buffer, &file_path).map(Some)
+}
+
+/// Replaces the checkpoint in the given partition's directory with the given log start offset.
+///
+/// The checkpoint is written to a temporary file, which is synced and renamed over the old one,
+/// so an interrupted write leaves either the old or the new checkpoint.
+pub fn write_log_start_offset(vfs: &dyn Vfs, path: &str, offset: usize) -> Result<(), Error> {
+    let file_path = format!("{}/{}", path, LOG_START_OFFSET_FILE);
+    let tmp_path = format!("{}.tmp", file_path);
+
+    let file = vfs.open(&tmp_path)?;
+    file.set_len(0)?;
+    file.write_all_at(&format::encode_checkpoint(offset), 0)?;
+    file.sync_data()?;
+    vfs.rename(&tmp_path, &file_path)?;
+
+    // the rename itself is durable only after the directory is synced.
+    vfs.sync_dir(path)
+}
+
+/// Removes the checkpoint from the given partition's directory, if there is one.
+pub fn remove_log_start_offset(vfs: &dyn Vfs, path: &str) -> Result<(), Error> {
+    match vfs.remove_file(&format!("{}/{}", path, LOG_START_OFFSET_FILE)) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
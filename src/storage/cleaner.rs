@@ -0,0 +1,194 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind},
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+
+use crate::core::message::{Message, RawData};
+
+use super::{
+    compression::Compression,
+    partition::{PartitionConfig, Segments},
+    segment::{Segment, SegmentLimits},
+    vfs::Vfs,
+};
+
+/// CLEANER_DIR is the directory inside of a partition, where the cleaned segments are written
+/// before they replace the original ones.
+const CLEANER_DIR: &str = "cleaner";
+
+/// CLEANER_BATCH_SIZE is the amount of messages written to a cleaned segment at once.
+const CLEANER_BATCH_SIZE: usize = 1000;
+
+/// Cleaner compacts the closed segments of a partition, so that only the last message
+/// for each key is left in them.
+///
+/// Offsets and timestamps of the left messages are not changed, so the offsets in a compacted
+/// segment have gaps. The last message of every segment is always left, so that the range
+/// of offsets covered by the segment stays the same. A message with an empty value is
+/// a tombstone: it removes all of the previous messages with its key and is itself removed
+/// after the tombstone retention. Messages without a key are never removed.
+/// The left messages are compressed with the partition's codec.
+pub struct Cleaner<'a> {
+    vfs: Arc<dyn Vfs>,
+    path: &'a str,
+    config: &'a PartitionConfig,
+    tombstone_retention: Duration,
+    compression: Compression,
+    now: DateTime<Utc>,
+}
+
+impl<'a> Cleaner<'a> {
+    pub fn new(path: &'a str, config: &'a PartitionConfig) -> Self {
+        Self {
+            vfs: config.vfs.clone(),
+            path,
+            config,
+            tombstone_retention: config.tombstone_retention,
+            compression: config.compression,
+            now: Utc::now(),
+        }
+    }
+
+    /// Compacts the closed segments and returns the amount of removed messages.
+    pub fn compact(&self, segments: &Segments) -> Result<usize, Error> {
+        // the segments are cleaned without holding the partition's lock, so that the writes
+        // are not blocked, and only the replacement of a segment takes it.
+        let snapshot: Vec<(usize, Arc<RwLock<Segment>>)> = segments
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(&base_offset, s)| (base_offset, s.clone()))
+            .collect();
+
+        let Some((_, closed)) = snapshot.split_last() else {
+            return Ok(0);
+        };
+
+        // the active segment is not compacted, but the messages in it still replace
+        // the older ones with the same keys.
+        let mut latest: HashMap<RawData, usize> = HashMap::new();
+        for (_, segment) in snapshot.iter() {
+            segment.read().unwrap().for_each_message(|message| {
+                if let Some(key) = message.key {
+                    latest.insert(key, message.offset);
+                }
+                Ok(())
+            })?;
+        }
+
+        let cleaner_path = format!("{}/{}", self.path, CLEANER_DIR);
+        self.remove_dir(&cleaner_path)?;
+        self.vfs.create_dir_all(&cleaner_path)?;
+
+        let mut removed = 0;
+        for (base_offset, segment) in closed.iter() {
+            let cleaned = self.clean(&segment.read().unwrap(), &cleaner_path, &latest)?;
+            if cleaned == 0 {
+                continue;
+            }
+
+            let mut segments = segments.write().unwrap();
+            // the segment could have been deleted by the retention or replaced
+            // by the truncation in the meantime.
+            if !segments
+                .get(base_offset)
+                .is_some_and(|s| Arc::ptr_eq(s, segment))
+            {
+                continue;
+            }
+
+            Segment::replace_files(self.vfs.as_ref(), &cleaner_path, self.path, *base_offset)?;
+            self.config.sync_dir(self.path)?;
+            let segment = Segment::new(self.vfs.clone(), self.path.to_string(), *base_offset)?;
+            segments.insert(*base_offset, Arc::new(RwLock::new(segment)));
+
+            removed += cleaned;
+        }
+
+        self.remove_dir(&cleaner_path)?;
+        Ok(removed)
+    }
+
+    // clean writes the messages of the segment that should be kept to a new segment
+    // in the cleaner's directory and returns the amount of removed messages.
+    // Nothing is written if there is nothing to remove.
+    fn clean(
+        &self,
+        segment: &Segment,
+        cleaner_path: &str,
+        latest: &HashMap<RawData, usize>,
+    ) -> Result<usize, Error> {
+        let Some(last_offset) = segment.next_offset().checked_sub(1) else {
+            return Ok(0);
+        };
+
+        let mut removed = 0;
+        segment.for_each_message(|message| {
+            if !self.should_keep(&message, latest, last_offset) {
+                removed += 1;
+            }
+            Ok(())
+        })?;
+        if removed == 0 {
+            return Ok(0);
+        }
+
+        let mut cleaned = Segment::new(
+            self.vfs.clone(),
+            cleaner_path.to_string(),
+            segment.base_offset(),
+        )?;
+        let limits = SegmentLimits::unlimited();
+        let mut batch = Vec::with_capacity(CLEANER_BATCH_SIZE);
+
+        segment.for_each_message(|message| {
+            if self.should_keep(&message, latest, last_offset) {
+                batch.push(message);
+            }
+            if batch.len() >= CLEANER_BATCH_SIZE {
+                cleaned.write_batch(&mut batch.drain(..).peekable(), &limits, self.compression)?;
+            }
+            Ok(())
+        })?;
+        cleaned.write_batch(&mut batch.drain(..).peekable(), &limits, self.compression)?;
+        cleaned.sync()?;
+
+        Ok(removed)
+    }
+
+    fn should_keep(
+        &self,
+        message: &Message,
+        latest: &HashMap<RawData, usize>,
+        last_offset: usize,
+    ) -> bool {
+        if message.offset == last_offset {
+            return true;
+        }
+
+        let Some(key) = &message.key else {
+            return true;
+        };
+        if latest.get(key) != Some(&message.offset) {
+            return false;
+        }
+
+        let is_tombstone = message.value.is_empty();
+        let is_expired = (self.now - message.timestamp)
+            .to_std()
+            .is_ok_and(|age| age >= self.tombstone_retention);
+
+        !(is_tombstone && is_expired)
+    }
+
+    fn remove_dir(&self, path: &str) -> Result<(), Error> {
+        match self.vfs.remove_dir_all(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
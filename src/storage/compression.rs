@@ -0,0 +1,125 @@
+use core::fmt;
+use std::{
+    io::{Error, ErrorKind, Read, Write},
+    ops::AddAssign,
+};
+
+/// Compression is the codec, with which the records of a batch are compressed as a whole.
+///
+/// The codec is stored in the header of every batch, so the batches compressed
+/// with different codecs, or not compressed at all, could be stored in the same segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Lz4,
+    Zstd,
+    Snappy,
+}
+
+impl Compression {
+    /// Returns the id of the codec, that is stored in the batch's header.
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::None => 0,
+            Self::Gzip => 1,
+            Self::Lz4 => 2,
+            Self::Zstd => 3,
+            Self::Snappy => 4,
+        }
+    }
+
+    /// Returns the codec with the given id, or None if it is unknown.
+    pub fn from_id(id: u8) -> Option<Self> {
+        match id {
+            0 => Some(Self::None),
+            1 => Some(Self::Gzip),
+            2 => Some(Self::Lz4),
+            3 => Some(Self::Zstd),
+            4 => Some(Self::Snappy),
+            _ => None,
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Gzip => {
+                let mut encoder =
+                    flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            }
+            Self::Lz4 => Ok(lz4_flex::compress_prepend_size(data)),
+            Self::Zstd => zstd::encode_all(data, zstd::DEFAULT_COMPRESSION_LEVEL),
+            Self::Snappy => snap::raw::Encoder::new()
+                .compress_vec(data)
+                .map_err(|e| self.error(e)),
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::None => Ok(data.to_vec()),
+            Self::Gzip => {
+                let mut buffer = Vec::new();
+                flate2::read::GzDecoder::new(data).read_to_end(&mut buffer)?;
+                Ok(buffer)
+            }
+            Self::Lz4 => lz4_flex::decompress_size_prepended(data).map_err(|e| self.error(e)),
+            Self::Zstd => zstd::decode_all(data),
+            Self::Snappy => snap::raw::Decoder::new()
+                .decompress_vec(data)
+                .map_err(|e| self.error(e)),
+        }
+    }
+
+    fn error<E: fmt::Display>(&self, e: E) -> Error {
+        Error::new(
+            ErrorKind::InvalidData,
+            format!("{:?} codec failed: {}", self, e),
+        )
+    }
+}
+
+/// CompressionStats tells how much the written messages were compressed.
+///
+/// Both sizes are of the records only, the headers of the batches are not counted,
+/// since they are never compressed.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct CompressionStats {
+    /// raw_bytes is the size of the serialized messages before the compression.
+    pub raw_bytes: usize,
+    /// stored_bytes is the size of the compressed messages as they were written to the log.
+    pub stored_bytes: usize,
+}
+
+impl CompressionStats {
+    /// Returns how many times the stored messages are smaller than the raw ones.
+    pub fn ratio(&self) -> f64 {
+        if self.stored_bytes == 0 {
+            return 1.0;
+        }
+        self.raw_bytes as f64 / self.stored_bytes as f64
+    }
+}
+
+impl AddAssign for CompressionStats {
+    fn add_assign(&mut self, other: Self) {
+        self.raw_bytes += other.raw_bytes;
+        self.stored_bytes += other.stored_bytes;
+    }
+}
+
+impl fmt::Display for CompressionStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[Compression: Raw: {} bytes, Stored: {} bytes, Ratio: {:.2}]",
+            self.raw_bytes,
+            self.stored_bytes,
+            self.ratio()
+        )
+    }
+}
@@ -0,0 +1,173 @@
+use core::fmt;
+use std::io::{Error, ErrorKind};
+
+/// StorageError describes the failures that are specific to the storage.
+///
+/// It is carried inside of std::io::Error, so the storage API keeps returning io errors,
+/// and StorageError::of could be used to tell these failures apart from the rest.
+#[derive(Debug)]
+pub enum StorageError {
+    /// CorruptRecord means that a record read from the disk does not match its checksum.
+    CorruptRecord {
+        segment: String,
+        /// offset is the base offset of the damaged batch as read from the disk,
+        /// so it could be damaged as well.
+        offset: usize,
+        /// position is the physical position of the damaged record in the log.
+        position: usize,
+    },
+    /// UnsupportedCompression means that a record is compressed with a codec
+    /// that is not known to this version of the storage.
+    UnsupportedCompression {
+        segment: String,
+        codec: u8,
+        /// position is the physical position of the record in the log.
+        position: usize,
+    },
+    /// UnsupportedAttributes means that a record has the attributes
+    /// that are not known to this version of the storage.
+    UnsupportedAttributes {
+        segment: String,
+        attributes: u8,
+        /// position is the physical position of the record in the log.
+        position: usize,
+    },
+    /// UnsupportedFormat means that a segment file has a version of the format
+    /// that could not be read, the version is 0 for the files without the header.
+    UnsupportedFormat { path: String, version: u16 },
+    /// InvalidSegmentFile means that a partition contains a segment file with a name
+    /// that is not a base offset.
+    InvalidSegmentFile { path: String },
+    /// OrphanIndexFile means that a partition contains an index file without the log.
+    OrphanIndexFile { path: String },
+    /// StaleIndexFile means that an index file has the entries, which do not match the log.
+    StaleIndexFile { path: String },
+    /// SegmentGap means that the offsets between the end of a segment and the next one are missing.
+    SegmentGap {
+        partition: String,
+        /// expected is the offset, at which the next segment should have started.
+        expected: usize,
+        base_offset: usize,
+    },
+    /// SegmentOverlap means that a segment starts before the end of the previous one.
+    SegmentOverlap {
+        partition: String,
+        /// expected is the offset, at which the next segment should have started.
+        expected: usize,
+        base_offset: usize,
+    },
+    /// OffsetOutOfRange means that the requested offset is not in the log, either because
+    /// it was already deleted or because it was not written yet.
+    OffsetOutOfRange {
+        offset: usize,
+        log_start_offset: usize,
+        log_end_offset: usize,
+    },
+}
+
+impl StorageError {
+    /// Returns the StorageError carried by the given error, if there is one.
+    pub fn of(e: &Error) -> Option<&StorageError> {
+        e.get_ref()?.downcast_ref()
+    }
+
+    fn kind(&self) -> ErrorKind {
+        match self {
+            Self::CorruptRecord { .. }
+            | Self::InvalidSegmentFile { .. }
+            | Self::OrphanIndexFile { .. }
+            | Self::StaleIndexFile { .. }
+            | Self::SegmentGap { .. }
+            | Self::SegmentOverlap { .. } => ErrorKind::InvalidData,
+            Self::UnsupportedCompression { .. }
+            | Self::UnsupportedAttributes { .. }
+            | Self::UnsupportedFormat { .. } => ErrorKind::Unsupported,
+            Self::OffsetOutOfRange { .. } => ErrorKind::InvalidInput,
+        }
+    }
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::CorruptRecord {
+                segment,
+                offset,
+                position,
+            } => write!(
+                f,
+                "checksum mismatch in the record at {} with base offset {} in {}",
+                position, offset, segment
+            ),
+            Self::UnsupportedCompression {
+                segment,
+                codec,
+                position,
+            } => write!(
+                f,
+                "record at {} in {} is compressed with unknown codec {}",
+                position, segment, codec
+            ),
+            Self::UnsupportedAttributes {
+                segment,
+                attributes,
+                position,
+            } => write!(
+                f,
+                "record at {} in {} has unknown attributes {:#04x}",
+                position, segment, attributes
+            ),
+            Self::UnsupportedFormat { path, version } => write!(
+                f,
+                "`{}` has format version {}, but only {} is supported",
+                path,
+                version,
+                super::format::FORMAT_VERSION
+            ),
+            Self::InvalidSegmentFile { path } => {
+                write!(f, "segment file `{}` is not named by a base offset", path)
+            }
+            Self::OrphanIndexFile { path } => {
+                write!(f, "index file `{}` has no log file", path)
+            }
+            Self::StaleIndexFile { path } => {
+                write!(f, "index file `{}` does not match the log", path)
+            }
+            Self::SegmentGap {
+                partition,
+                expected,
+                base_offset,
+            } => write!(
+                f,
+                "offsets from {} to {} are missing in `{}`",
+                expected, base_offset, partition
+            ),
+            Self::SegmentOverlap {
+                partition,
+                expected,
+                base_offset,
+            } => write!(
+                f,
+                "segment {} overlaps with the previous one ending at {} in `{}`",
+                base_offset, expected, partition
+            ),
+            Self::OffsetOutOfRange {
+                offset,
+                log_start_offset,
+                log_end_offset,
+            } => write!(
+                f,
+                "offset {} is out of the log range [{}, {})",
+                offset, log_start_offset, log_end_offset
+            ),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<StorageError> for Error {
+    fn from(value: StorageError) -> Self {
+        Error::new(value.kind(), value)
+    }
+}
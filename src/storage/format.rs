@@ -0,0 +1,641 @@
+use std::io::{Error, ErrorKind};
+
+use chrono::{DateTime, TimeZone, Utc};
+
+use crate::core::message::{Headers, Message};
+
+use super::{compression::Compression, error::StorageError, vfs::VfsFile};
+
+/// FORMAT_VERSION is the version of the layout of the segment files written by this storage.
+///
+/// Every .log, .index, .timeindex, .checkpoint and .manifest file starts with a header of FILE_HEADER_SIZE bytes:
+///
+/// | bytes | field    | value                                                       |
+/// |-------|----------|-------------------------------------------------------------|
+/// | 0..4  | magic    | `DMQL` for a log, `DMQI` for an offset index, `DMQT` for a time index, `DMQC` for a checkpoint, `DMQM` for a manifest |
+/// | 4..6  | version  | u16, FORMAT_VERSION                                         |
+/// | 6..8  | reserved | u16, always 0                                               |
+///
+/// All of the integers in the files are little-endian and have a fixed width,
+/// the layouts of the entries are described by the functions that encode them.
+/// The files written by the first version of the storage have no header, their version is 0,
+/// and they are upgraded on load. The version 1 stores the batches of records with the offsets
+/// and timestamps relative to the batch.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// FILE_HEADER_SIZE is the size of the header at the beginning of every segment file,
+/// i.e. the position of the first entry.
+pub const FILE_HEADER_SIZE: usize = 8;
+
+/// BATCH_HEADER_SIZE is the size of the header written before every batch of records in a log.
+pub const BATCH_HEADER_SIZE: usize = 41;
+
+/// INDEX_ENTRY_SIZE is the size of an entry in both of the indexes.
+pub const INDEX_ENTRY_SIZE: usize = 16;
+
+/// CHECKPOINT_SIZE is the size of a checkpoint file: the file header followed by an offset as u64.
+pub const CHECKPOINT_SIZE: usize = FILE_HEADER_SIZE + 8;
+
+/// MANIFEST_SIZE is the size of a segment manifest, i.e. the file header and SegmentManifest.
+pub const MANIFEST_SIZE: usize = FILE_HEADER_SIZE + 32;
+
+/// FileKind is the kind of a segment file, each has its own magic bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FileKind {
+    Log,
+    OffsetIndex,
+    TimeIndex,
+    Checkpoint,
+    Manifest,
+}
+
+impl FileKind {
+    fn magic(&self) -> &'static [u8; 4] {
+        match self {
+            Self::Log => b"DMQL",
+            Self::OffsetIndex => b"DMQI",
+            Self::TimeIndex => b"DMQT",
+            Self::Checkpoint => b"DMQC",
+            Self::Manifest => b"DMQM",
+        }
+    }
+}
+
+/// Reads the version of the given file, 0 means that the file has no header.
+/// An empty file is of the current version, since it is written as one, and so is a file
+/// with only a part of the header, which is left by a torn write of the header.
+pub fn read_version(file: &dyn VfsFile, kind: FileKind) -> Result<u16, Error> {
+    let len = file.size()? as usize;
+    if len < FILE_HEADER_SIZE {
+        let mut header = vec![0u8; len];
+        file.read_exact_at(&mut header, 0)?;
+
+        return match file_header(kind).starts_with(&header) {
+            true => Ok(FORMAT_VERSION),
+            false => Ok(0),
+        };
+    }
+
+    let mut header = [0u8; FILE_HEADER_SIZE];
+    file.read_exact_at(&mut header, 0)?;
+
+    if &header[0..4] != kind.magic() {
+        return Ok(0);
+    }
+    Ok(u16::from_le_bytes([header[4], header[5]]))
+}
+
+/// Checks that the given file is of the current version and writes the header to it,
+/// if the file does not have the whole header yet. The header is synced right away,
+/// so that a torn write after it could not leave the file without it.
+pub fn version_guard(file: &dyn VfsFile, kind: FileKind, path: &str) -> Result<(), Error> {
+    let version = read_version(file, kind)?;
+    if version != FORMAT_VERSION {
+        return Err(StorageError::UnsupportedFormat {
+            path: path.to_string(),
+            version,
+        }
+        .into());
+    }
+
+    if file.size()? < FILE_HEADER_SIZE as u64 {
+        write_file_header(file, kind)?;
+        file.sync_data()?;
+    }
+
+    Ok(())
+}
+
+/// Replaces the content of the file with the header of the current version.
+pub fn write_file_header(file: &dyn VfsFile, kind: FileKind) -> Result<(), Error> {
+    file.set_len(0)?;
+    file.write_all_at(&file_header(kind), 0)
+}
+
+/// Encodes a checkpoint file, which stores the given offset.
+pub fn encode_checkpoint(offset: usize) -> [u8; CHECKPOINT_SIZE] {
+    let mut checkpoint = [0u8; CHECKPOINT_SIZE];
+    checkpoint[..FILE_HEADER_SIZE].copy_from_slice(&file_header(FileKind::Checkpoint));
+    checkpoint[FILE_HEADER_SIZE..].copy_from_slice(&(offset as u64).to_le_bytes());
+    checkpoint
+}
+
+/// Decodes the offset from the checkpoint file with the given path.
+pub fn decode_checkpoint(buffer: &[u8], path: &str) -> Result<usize, Error> {
+    let mut decoder = small_file_decoder(buffer, FileKind::Checkpoint, CHECKPOINT_SIZE, path)?;
+    Ok(decoder.u64()? as usize)
+}
+
+/// SegmentManifest describes a segment copied to the remote storage, it is stored
+/// along with the segment's files after the file header:
+///
+/// | field            | type                                                         |
+/// |------------------|--------------------------------------------------------------|
+/// | base offset      | u64                                                          |
+/// | next offset      | u64, the offset after the last message of the segment        |
+/// | latest timestamp | i64, in nanoseconds since the Unix epoch, i64::MIN for none  |
+/// | size             | u64, the size of the segment's log in bytes                  |
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentManifest {
+    pub base_offset: usize,
+    pub next_offset: usize,
+    pub latest_timestamp: Option<DateTime<Utc>>,
+    pub size_in_bytes: usize,
+}
+
+impl SegmentManifest {
+    pub fn encode(&self) -> [u8; MANIFEST_SIZE] {
+        let latest_timestamp = self
+            .latest_timestamp
+            .and_then(|t| t.timestamp_nanos_opt())
+            .unwrap_or(i64::MIN);
+
+        let mut manifest = [0u8; MANIFEST_SIZE];
+        manifest[..FILE_HEADER_SIZE].copy_from_slice(&file_header(FileKind::Manifest));
+        manifest[8..16].copy_from_slice(&(self.base_offset as u64).to_le_bytes());
+        manifest[16..24].copy_from_slice(&(self.next_offset as u64).to_le_bytes());
+        manifest[24..32].copy_from_slice(&latest_timestamp.to_le_bytes());
+        manifest[32..40].copy_from_slice(&(self.size_in_bytes as u64).to_le_bytes());
+        manifest
+    }
+
+    /// Decodes the manifest file with the given path.
+    pub fn decode(buffer: &[u8], path: &str) -> Result<Self, Error> {
+        let mut decoder = small_file_decoder(buffer, FileKind::Manifest, MANIFEST_SIZE, path)?;
+
+        Ok(Self {
+            base_offset: decoder.u64()? as usize,
+            next_offset: decoder.u64()? as usize,
+            latest_timestamp: match decoder.i64()? {
+                i64::MIN => None,
+                nanos => Some(Utc.timestamp_nanos(nanos)),
+            },
+            size_in_bytes: decoder.u64()? as usize,
+        })
+    }
+}
+
+fn file_header(kind: FileKind) -> [u8; FILE_HEADER_SIZE] {
+    let mut header = [0u8; FILE_HEADER_SIZE];
+    header[0..4].copy_from_slice(kind.magic());
+    header[4..6].copy_from_slice(&FORMAT_VERSION.to_le_bytes());
+    header
+}
+
+// small_file_decoder checks the size and the header of a file, which is read whole,
+// and returns the decoder of the content after the header.
+fn small_file_decoder<'a>(
+    buffer: &'a [u8],
+    kind: FileKind,
+    size: usize,
+    path: &str,
+) -> Result<Decoder<'a>, Error> {
+    if buffer.len() != size {
+        return Err(invalid(format!(
+            "file `{}` has {} bytes instead of {}",
+            path,
+            buffer.len(),
+            size
+        )));
+    }
+
+    let mut decoder = Decoder::new(buffer);
+    let magic = decoder.bytes(4)?;
+    let version = u16::from_le_bytes(decoder.array()?);
+    if magic != kind.magic() || version != FORMAT_VERSION {
+        return Err(StorageError::UnsupportedFormat {
+            path: path.to_string(),
+            version,
+        }
+        .into());
+    }
+    decoder.bytes(2)?;
+
+    Ok(decoder)
+}
+
+/// BatchHeader is written before every batch of records in a log:
+///
+/// | field             | type                                                       |
+/// |-------------------|------------------------------------------------------------|
+/// | base offset       | u64, the offset of the first record                        |
+/// | size              | u32, the size of the stored records after the header       |
+/// | crc               | u32, CRC32C of the rest of the header and the stored records |
+/// | attributes        | u8, the id of the codec the records are compressed with in the lowest 3 bits, the rest are 0 |
+/// | last offset delta | u32, the offset of the last record minus the base offset   |
+/// | base timestamp    | i64, the timestamp of the first record in nanoseconds since the Unix epoch |
+/// | max timestamp     | i64, the greatest timestamp of the records                 |
+/// | records           | u32, the amount of records                                 |
+///
+/// The stored records are the records encoded by BatchBuilder::encode and then compressed as a whole.
+/// The offsets in a batch are increasing, but could have gaps after a compaction.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BatchHeader {
+    pub base_offset: usize,
+    pub size: usize,
+    pub crc: u32,
+    pub attributes: u8,
+    pub last_offset_delta: usize,
+    pub base_timestamp: i64,
+    pub max_timestamp: i64,
+    pub records: usize,
+}
+
+impl BatchHeader {
+    /// CODEC_MASK is the bits of the attributes, which hold the id of the codec.
+    pub const CODEC_MASK: u8 = 0x07;
+
+    // CRC_START is the position in the header, from which the CRC32C is calculated.
+    const CRC_START: usize = 16;
+
+    /// Returns the offset of the last record in the batch.
+    pub fn last_offset(&self) -> usize {
+        self.base_offset + self.last_offset_delta
+    }
+
+    /// Returns the size of the whole batch in the log.
+    pub fn batch_size(&self) -> usize {
+        BATCH_HEADER_SIZE + self.size
+    }
+
+    /// Encodes the header and sets its crc to the CRC32C of it and the given stored records.
+    pub fn encode(&mut self, records: &[u8]) -> Result<[u8; BATCH_HEADER_SIZE], Error> {
+        let size = u32::try_from(self.size).map_err(|_| too_long("batch", self.size))?;
+        let last_offset_delta = u32::try_from(self.last_offset_delta)
+            .map_err(|_| too_long("batch offset range", self.last_offset_delta))?;
+        let count = u32::try_from(self.records).map_err(|_| too_long("batch", self.records))?;
+
+        let mut header = [0u8; BATCH_HEADER_SIZE];
+        header[0..8].copy_from_slice(&(self.base_offset as u64).to_le_bytes());
+        header[8..12].copy_from_slice(&size.to_le_bytes());
+        header[16] = self.attributes;
+        header[17..21].copy_from_slice(&last_offset_delta.to_le_bytes());
+        header[21..29].copy_from_slice(&self.base_timestamp.to_le_bytes());
+        header[29..37].copy_from_slice(&self.max_timestamp.to_le_bytes());
+        header[37..41].copy_from_slice(&count.to_le_bytes());
+
+        self.crc = Self::checksum(&header, records);
+        header[12..16].copy_from_slice(&self.crc.to_le_bytes());
+        Ok(header)
+    }
+
+    pub fn decode(header: &[u8]) -> Result<Self, Error> {
+        let mut decoder = Decoder::new(header);
+        Ok(Self {
+            base_offset: decoder.u64()? as usize,
+            size: decoder.u32()? as usize,
+            crc: decoder.u32()?,
+            attributes: decoder.u8()?,
+            last_offset_delta: decoder.u32()? as usize,
+            base_timestamp: decoder.i64()?,
+            max_timestamp: decoder.i64()?,
+            records: decoder.u32()? as usize,
+        })
+    }
+
+    /// Calculates the CRC32C of the encoded header and the stored records.
+    pub fn checksum(header: &[u8], records: &[u8]) -> u32 {
+        let crc = crc32c::crc32c(&header[Self::CRC_START..BATCH_HEADER_SIZE]);
+        crc32c::crc32c_append(crc, records)
+    }
+}
+
+/// BatchBuilder encodes the messages into the records of a batch one by one.
+///
+/// The base offset and timestamp of the batch are taken from the first message.
+#[derive(Default)]
+pub struct BatchBuilder {
+    header: BatchHeader,
+    records: Vec<u8>,
+}
+
+impl BatchBuilder {
+    /// Returns true if no records were pushed yet.
+    pub fn is_empty(&self) -> bool {
+        self.header.records == 0
+    }
+
+    /// Returns the size of the pushed records before the compression.
+    pub fn records_size(&self) -> usize {
+        self.records.len()
+    }
+
+    /// Encodes the message as a record of this batch in the following layout:
+    ///
+    /// | field           | type                                                      |
+    /// |-----------------|-----------------------------------------------------------|
+    /// | offset delta    | varint, the offset minus the base offset of the batch     |
+    /// | timestamp delta | signed varint, the timestamp minus the base timestamp     |
+    /// | key length      | signed varint, -1 if there is no key                      |
+    /// | key             | bytes                                                     |
+    /// | value length    | varint                                                    |
+    /// | value           | bytes                                                     |
+    /// | headers count   | varint                                                    |
+    /// | headers         | name length varint, name in UTF-8, value length varint, value |
+    ///
+    /// A varint is LEB128, the signed ones are zigzag-encoded first.
+    /// The record is not added to the batch until it is pushed.
+    pub fn encode(&self, message: &Message) -> Result<Vec<u8>, Error> {
+        let timestamp = timestamp_nanos(message)?;
+        let (base_offset, base_timestamp) = if self.is_empty() {
+            (message.offset, timestamp)
+        } else {
+            (self.header.base_offset, self.header.base_timestamp)
+        };
+        if message.offset < base_offset {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "offset {} is before the base offset {} of the batch",
+                    message.offset, base_offset
+                ),
+            ));
+        }
+
+        // the timestamps of a batch could be too far apart for their difference to fit into i64.
+        let timestamp_delta = timestamp.checked_sub(base_timestamp).ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "timestamp {} is too far from the base timestamp of the batch",
+                    message.timestamp
+                ),
+            )
+        })?;
+
+        let mut record = Vec::with_capacity(message.value.len() + 16);
+        put_uvarint(&mut record, (message.offset - base_offset) as u64);
+        put_varint(&mut record, timestamp_delta);
+        match &message.key {
+            Some(key) => {
+                put_varint(&mut record, key.len() as i64);
+                record.extend_from_slice(key);
+            }
+            None => put_varint(&mut record, -1),
+        }
+        put_bytes(&mut record, &message.value);
+
+        put_uvarint(&mut record, message.headers.len() as u64);
+        for (name, value) in message.headers.iter() {
+            put_bytes(&mut record, name.as_bytes());
+            put_bytes(&mut record, value);
+        }
+
+        Ok(record)
+    }
+
+    /// Adds the record encoded by BatchBuilder::encode for the given message to the batch.
+    pub fn push(&mut self, message: &Message, record: &[u8]) -> Result<(), Error> {
+        let timestamp = timestamp_nanos(message)?;
+        if self.is_empty() {
+            self.header.base_offset = message.offset;
+            self.header.base_timestamp = timestamp;
+            self.header.max_timestamp = timestamp;
+        }
+
+        self.header.last_offset_delta = message.offset - self.header.base_offset;
+        self.header.max_timestamp = self.header.max_timestamp.max(timestamp);
+        self.header.records += 1;
+        self.records.extend_from_slice(record);
+        Ok(())
+    }
+
+    /// Compresses the records with the given codec and returns the header of the batch
+    /// along with the whole batch as it is written to the log.
+    pub fn build(mut self, compression: Compression) -> Result<(BatchHeader, Vec<u8>), Error> {
+        let stored = compression.compress(&self.records)?;
+        self.header.size = stored.len();
+        self.header.attributes = compression.id();
+
+        let mut batch = Vec::with_capacity(BATCH_HEADER_SIZE + stored.len());
+        batch.extend(self.header.encode(&stored)?);
+        batch.extend(stored);
+        Ok((self.header, batch))
+    }
+}
+
+/// Decodes the records of the batch with the given header, encoded by BatchBuilder::encode.
+pub fn decode_records(header: &BatchHeader, buffer: &[u8]) -> Result<Vec<Message>, Error> {
+    let mut decoder = Decoder::new(buffer);
+    let mut messages = Vec::with_capacity(header.records.min(buffer.len()));
+
+    for _ in 0..header.records {
+        let offset = header
+            .base_offset
+            .checked_add(decoder.uvarint()? as usize)
+            .ok_or_else(|| {
+                invalid(format!(
+                    "offset delta overflows in batch {}",
+                    header.base_offset
+                ))
+            })?;
+        let timestamp = header
+            .base_timestamp
+            .checked_add(decoder.varint()?)
+            .ok_or_else(|| {
+                invalid(format!(
+                    "timestamp delta overflows in batch {}",
+                    header.base_offset
+                ))
+            })?;
+        let timestamp = Utc.timestamp_nanos(timestamp);
+        let key = match decoder.varint()? {
+            -1 => None,
+            len if len >= 0 => Some(decoder.bytes(len as usize)?.to_vec()),
+            len => return Err(invalid(format!("key length {} is negative", len))),
+        };
+        let value = decoder.sized_bytes()?.to_vec();
+
+        let count = decoder.uvarint()? as usize;
+        let mut headers = Headers::with_capacity(count.min(buffer.len()));
+        for _ in 0..count {
+            let name = String::from_utf8(decoder.sized_bytes()?.to_vec())
+                .map_err(|e| invalid(format!("header name is not UTF-8: {}", e)))?;
+            headers.push((name, decoder.sized_bytes()?.to_vec()));
+        }
+
+        messages.push(Message::new(offset, timestamp, key, value, headers));
+    }
+
+    if !decoder.is_empty() {
+        return Err(invalid(format!(
+            "{} bytes are left after the records of batch {}",
+            decoder.remaining(),
+            header.base_offset
+        )));
+    }
+
+    Ok(messages)
+}
+
+/// Returns the timestamp of the message in nanoseconds since the Unix epoch.
+pub fn timestamp_nanos(message: &Message) -> Result<i64, Error> {
+    message.timestamp.timestamp_nanos_opt().ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("timestamp {} is out of range", message.timestamp),
+        )
+    })
+}
+
+/// Checks that the timestamps of the messages could be written in any batch made of them:
+/// each of them fits into nanoseconds, and so does the distance between any two of them.
+pub fn validate_timestamps(messages: &[Message]) -> Result<(), Error> {
+    let mut range: Option<(i64, i64)> = None;
+    for message in messages {
+        let timestamp = timestamp_nanos(message)?;
+        let (min, max) = range.unwrap_or((timestamp, timestamp));
+        let (min, max) = (min.min(timestamp), max.max(timestamp));
+        if max.checked_sub(min).is_none() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "timestamp {} is too far from the other timestamps of the batch",
+                    message.timestamp
+                ),
+            ));
+        }
+        range = Some((min, max));
+    }
+    Ok(())
+}
+
+/// Encodes an entry of the offset index: the offset as u64 and the position in the log as u64.
+pub fn encode_offset_entry(offset: usize, position: usize) -> [u8; 16] {
+    let mut entry = [0u8; 16];
+    entry[0..8].copy_from_slice(&(offset as u64).to_le_bytes());
+    entry[8..16].copy_from_slice(&(position as u64).to_le_bytes());
+    entry
+}
+
+/// Decodes an entry of the offset index into (offset, position).
+pub fn decode_offset_entry(entry: &[u8]) -> Result<(usize, usize), Error> {
+    let mut decoder = Decoder::new(entry);
+    Ok((decoder.u64()? as usize, decoder.u64()? as usize))
+}
+
+/// Encodes an entry of the time index: the timestamp in nanoseconds as i64
+/// and the offset as u64.
+pub fn encode_time_entry(timestamp: i64, offset: usize) -> [u8; 16] {
+    let mut entry = [0u8; 16];
+    entry[0..8].copy_from_slice(&timestamp.to_le_bytes());
+    entry[8..16].copy_from_slice(&(offset as u64).to_le_bytes());
+    entry
+}
+
+/// Decodes an entry of the time index into (timestamp, offset).
+pub fn decode_time_entry(entry: &[u8]) -> Result<(i64, usize), Error> {
+    let mut decoder = Decoder::new(entry);
+    Ok((decoder.i64()?, decoder.u64()? as usize))
+}
+
+fn put_uvarint(buffer: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buffer.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.push(value as u8);
+}
+
+fn put_varint(buffer: &mut Vec<u8>, value: i64) {
+    put_uvarint(buffer, ((value << 1) ^ (value >> 63)) as u64);
+}
+
+fn put_bytes(buffer: &mut Vec<u8>, bytes: &[u8]) {
+    put_uvarint(buffer, bytes.len() as u64);
+    buffer.extend_from_slice(bytes);
+}
+
+fn too_long(what: &str, len: usize) -> Error {
+    Error::new(
+        ErrorKind::InvalidInput,
+        format!("{} of {} bytes is too long", what, len),
+    )
+}
+
+fn invalid(message: String) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+/// Decoder reads the little-endian fields and varints from a buffer one after another.
+pub struct Decoder<'a> {
+    buffer: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new(buffer: &'a [u8]) -> Self {
+        Self { buffer }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.buffer.len()
+    }
+
+    pub fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if len > self.buffer.len() {
+            return Err(invalid(format!(
+                "expected {} bytes, but only {} are left",
+                len,
+                self.buffer.len()
+            )));
+        }
+
+        let (bytes, rest) = self.buffer.split_at(len);
+        self.buffer = rest;
+        Ok(bytes)
+    }
+
+    // sized_bytes reads the bytes prefixed with their length as a varint.
+    fn sized_bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.uvarint()? as usize;
+        self.bytes(len)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], Error> {
+        let mut array = [0u8; N];
+        array.copy_from_slice(self.bytes(N)?);
+        Ok(array)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    pub fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn i32(&mut self) -> Result<i32, Error> {
+        Ok(i32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    pub fn i64(&mut self) -> Result<i64, Error> {
+        Ok(i64::from_le_bytes(self.array()?))
+    }
+
+    fn uvarint(&mut self) -> Result<u64, Error> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        Err(invalid("varint is longer than 10 bytes".to_string()))
+    }
+
+    fn varint(&mut self) -> Result<i64, Error> {
+        let value = self.uvarint()?;
+        Ok((value >> 1) as i64 ^ -((value & 1) as i64))
+    }
+}
@@ -0,0 +1,334 @@
+use std::{
+    collections::VecDeque,
+    io::{Error, ErrorKind},
+    ops::Bound,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+use tokio::sync::watch;
+
+use crate::core::message::Message;
+
+use super::{error::StorageError, partition::Segments, segment::Segment, tiered::RemoteTier};
+
+/// Cursor is a segment along with the position of the next batch in it.
+type Cursor = (Arc<RwLock<Segment>>, usize);
+
+/// Truncations tells the iterators of a partition, to which offsets it was truncated.
+#[derive(Default)]
+pub(super) struct Truncations {
+    /// count is the amount of times the partition was truncated, it is read without the lock
+    /// to tell whether anything changed.
+    count: AtomicUsize,
+    /// lowest are the numbers of the truncations along with their offsets, which are lower
+    /// than the offsets of all of the later truncations, so both of them grow.
+    lowest: Mutex<Vec<(usize, usize)>>,
+}
+
+impl Truncations {
+    /// Records the truncation of the partition to the given offset.
+    pub fn record(&self, offset: usize) {
+        let mut lowest = self.lowest.lock().unwrap();
+        while lowest.last().is_some_and(|&(_, o)| o >= offset) {
+            lowest.pop();
+        }
+
+        let count = self.count.load(Ordering::Acquire) + 1;
+        lowest.push((count, offset));
+        self.count.store(count, Ordering::Release);
+    }
+
+    /// Returns the amount of times the partition was truncated.
+    pub fn count(&self) -> usize {
+        self.count.load(Ordering::Acquire)
+    }
+
+    /// Returns the amount of times the partition was truncated along with the lowest offset,
+    /// to which it was truncated after the given amount of truncations, if it was.
+    pub fn lowest_since(&self, seen: usize) -> (usize, Option<usize>) {
+        let lowest = self.lowest.lock().unwrap();
+        let offset = lowest
+            .iter()
+            .find(|&&(count, _)| count > seen)
+            .map(|&(_, offset)| offset);
+
+        (self.count.load(Ordering::Acquire), offset)
+    }
+}
+
+/// PartitionIter reads the messages of a partition sequentially, starting from an offset.
+///
+/// The log is read batch after batch, so every message is read once without a lookup
+/// in the indexes, and the segments are crossed in the order of their base offsets.
+/// The iterator does not borrow the partition, so the messages could be written while
+/// it is used: Iterator::next returns None at the end of the log, after which it could be
+/// called again, and PartitionIter::next_or_wait waits for the new messages instead.
+/// If the partition is truncated before the next offset, the iterator continues from
+/// the offset it was truncated to, even if the messages are written there again,
+/// and if the records before the next offset are deleted, it continues from the new
+/// log start offset.
+pub struct PartitionIter {
+    segments: Arc<Segments>,
+    /// end_offset is the offset after the last message written to the partition.
+    end_offset: watch::Receiver<usize>,
+    /// truncations tells, to which offsets the partition was truncated.
+    truncations: Arc<Truncations>,
+    /// seen_truncations is the amount of truncations, after which current was found.
+    seen_truncations: usize,
+    /// log_start_offset is the offset of the first message that could be read from the partition.
+    log_start_offset: Arc<AtomicUsize>,
+    /// tier holds the segments of the partition in the remote storage, from which the offsets
+    /// that are not on the local disk anymore are read.
+    tier: Option<Arc<RemoteTier>>,
+    /// next_offset is the smallest offset of the message that could be returned next,
+    /// in a compacted partition the next message could have a greater one.
+    next_offset: usize,
+    /// current is the segment that is being read and the position of the next batch in it.
+    current: Option<Cursor>,
+    /// buffered are the messages of the last read batch, which are not returned yet.
+    buffered: VecDeque<Message>,
+}
+
+impl PartitionIter {
+    pub(super) fn new(
+        segments: Arc<Segments>,
+        end_offset: watch::Receiver<usize>,
+        truncations: Arc<Truncations>,
+        log_start_offset: Arc<AtomicUsize>,
+        tier: Option<Arc<RemoteTier>>,
+        offset: usize,
+    ) -> Self {
+        let seen_truncations = truncations.count();
+        Self {
+            segments,
+            end_offset,
+            truncations,
+            seen_truncations,
+            log_start_offset,
+            tier,
+            next_offset: offset,
+            current: None,
+            buffered: VecDeque::new(),
+        }
+    }
+
+    /// Returns the smallest offset of the message that could be returned next.
+    pub fn next_offset(&self) -> usize {
+        self.next_offset
+    }
+
+    /// Returns the next message, waiting for it to be written if the end of the log is reached.
+    ///
+    /// The log is read on the blocking threads of the tokio runtime, so that the executor
+    /// is not blocked, and the buffered messages are returned without reading it.
+    /// An error is returned if the partition is dropped while waiting.
+    pub async fn next_or_wait(&mut self) -> Result<Message, Error> {
+        loop {
+            let next = match self.buffered_ready() {
+                true => self.next(),
+                false => self.next_blocking().await?,
+            };
+            if let Some(result) = next {
+                return result;
+            }
+
+            if self.end_offset.changed().await.is_err() {
+                return Err(Error::new(
+                    ErrorKind::BrokenPipe,
+                    format!(
+                        "partition is closed while waiting for offset {}",
+                        self.next_offset
+                    ),
+                ));
+            }
+        }
+    }
+
+    // buffered_ready tells whether the next message could be returned from the buffered ones
+    // without reading the log.
+    fn buffered_ready(&self) -> bool {
+        self.truncations.count() == self.seen_truncations
+            && self.next_offset >= self.log_start_offset.load(Ordering::Acquire)
+            && self.buffered.front().is_some_and(|message| {
+                message.offset >= self.next_offset && message.offset < *self.end_offset.borrow()
+            })
+    }
+
+    // next_blocking calls Iterator::next on a blocking thread. The iterator is moved there and
+    // back, and is left at the same offset without the position, if the call is cancelled.
+    async fn next_blocking(&mut self) -> Result<Option<Result<Message, Error>>, Error> {
+        let mut iter = std::mem::replace(
+            self,
+            Self {
+                segments: self.segments.clone(),
+                end_offset: self.end_offset.clone(),
+                truncations: self.truncations.clone(),
+                seen_truncations: self.seen_truncations,
+                log_start_offset: self.log_start_offset.clone(),
+                tier: self.tier.clone(),
+                next_offset: self.next_offset,
+                current: None,
+                buffered: VecDeque::new(),
+            },
+        );
+
+        let (iter, next) = tokio::task::spawn_blocking(move || {
+            let next = iter.next();
+            (iter, next)
+        })
+        .await
+        .map_err(|e| Error::other(format!("partition read failed: {}", e)))?;
+
+        *self = iter;
+        Ok(next)
+    }
+
+    // read_next returns the next message written before end_offset, or None if there is none.
+    fn read_next(&mut self, end_offset: usize) -> Result<Option<Message>, Error> {
+        loop {
+            self.truncation_guard();
+            self.log_start_guard();
+            if self.next_offset >= end_offset {
+                return Ok(None);
+            }
+
+            if let Some(message) = self.buffered.front() {
+                // the message is already in the log, but the write is not finished yet.
+                if message.offset >= end_offset {
+                    return Ok(None);
+                }
+
+                let message = self.buffered.pop_front().unwrap();
+                if message.offset < self.next_offset {
+                    continue;
+                }
+
+                self.next_offset = message.offset + 1;
+                return Ok(Some(message));
+            }
+
+            let (segment, position) = match &self.current {
+                Some(current) => current.clone(),
+                None => self.seek(end_offset)?,
+            };
+
+            let batch = segment.read().unwrap().read_batch(position)?;
+            let Some((messages, next_position)) = batch else {
+                let base_offset = segment.read().unwrap().base_offset();
+                match self.next_segment(base_offset)? {
+                    Some(next) => {
+                        self.current = Some(next);
+                        continue;
+                    }
+                    // the end of the active segment, the rest is not written yet.
+                    None => return Ok(None),
+                }
+            };
+
+            // the batch could have been read after the truncation from an invalid position.
+            if self.truncation_guard() {
+                continue;
+            }
+
+            self.current = Some((segment, next_position));
+            self.buffered.extend(messages);
+        }
+    }
+
+    // truncation_guard forgets the position and the buffered messages if the partition was
+    // truncated after they were read, moves the iterator back to the lowest offset it was
+    // truncated to, and tells whether it was.
+    fn truncation_guard(&mut self) -> bool {
+        if self.truncations.count() == self.seen_truncations {
+            return false;
+        }
+
+        let (truncations, lowest) = self.truncations.lowest_since(self.seen_truncations);
+        self.seen_truncations = truncations;
+        self.current = None;
+        self.buffered.clear();
+        if let Some(lowest) = lowest {
+            self.next_offset = self.next_offset.min(lowest);
+        }
+        true
+    }
+
+    // log_start_guard moves the iterator to the log start offset if the messages
+    // before it were deleted.
+    fn log_start_guard(&mut self) {
+        let log_start_offset = self.log_start_offset.load(Ordering::Acquire);
+        if self.next_offset >= log_start_offset {
+            return;
+        }
+
+        self.current = None;
+        self.buffered.clear();
+        self.next_offset = log_start_offset;
+    }
+
+    // seek finds the segment, which stores the next offset, and the position to read it from.
+    fn seek(&mut self, end_offset: usize) -> Result<Cursor, Error> {
+        if let Some(tier) = &self.tier {
+            if let Some(segment) = tier.segment(self.next_offset, self.local_start_offset())? {
+                let position = segment.read().unwrap().start_position(self.next_offset)?;
+                return Ok((segment, position));
+            }
+        }
+
+        let segments = self.segments.read().unwrap();
+
+        let Some((_, segment)) = segments.range(..=self.next_offset).next_back() else {
+            return Err(StorageError::OffsetOutOfRange {
+                offset: self.next_offset,
+                log_start_offset: segments.keys().next().copied().unwrap_or(end_offset),
+                log_end_offset: end_offset,
+            }
+            .into());
+        };
+
+        let position = segment.read().unwrap().start_position(self.next_offset)?;
+        Ok((segment.clone(), position))
+    }
+
+    // local_start_offset returns the base offset of the first segment on the local disk,
+    // the messages before it could only be read from the remote tier.
+    fn local_start_offset(&self) -> usize {
+        let segments = self.segments.read().unwrap();
+        segments.keys().next().copied().unwrap_or(usize::MAX)
+    }
+
+    // next_segment returns the segment after the one with the given base offset
+    // along with the position to start reading it from.
+    fn next_segment(&self, base_offset: usize) -> Result<Option<Cursor>, Error> {
+        if let Some(tier) = &self.tier {
+            if let Some(segment) = tier.segment_after(base_offset, self.local_start_offset())? {
+                let position = segment.read().unwrap().start_position(self.next_offset)?;
+                return Ok(Some((segment, position)));
+            }
+        }
+
+        let segments = self.segments.read().unwrap();
+
+        let Some((_, segment)) = segments
+            .range((Bound::Excluded(base_offset), Bound::Unbounded))
+            .next()
+        else {
+            return Ok(None);
+        };
+
+        let position = segment.read().unwrap().start_position(self.next_offset)?;
+        Ok(Some((segment.clone(), position)))
+    }
+}
+
+impl Iterator for PartitionIter {
+    type Item = Result<Message, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let end_offset = *self.end_offset.borrow_and_update();
+        self.read_next(end_offset).transpose()
+    }
+}
@@ -0,0 +1,84 @@
+use std::{io::Error, ops::Range};
+
+use chrono::{DateTime, Utc};
+
+use crate::core::message::Message;
+
+use super::{async_partition::Batch, partition::Partition};
+
+/// LogStorage is a log of messages, in which every appended message gets the next offset.
+///
+/// Partition stores the log in the files and MemoryLog keeps it in the memory, so the code
+/// written against the trait could be tested without touching the disk. Both of them return
+/// StorageError::OffsetOutOfRange for the offsets outside of the log and read the ranges
+/// by whole batches, but the batches could differ: MemoryLog keeps every append as one batch,
+/// while Partition splits an append at the segment rolls and into the batches of about 1MiB.
+/// So the byte limits of the range reads give the same result only for the appends that fit
+/// into one batch of the partition.
+pub trait LogStorage {
+    /// Appends the messages to the log and returns the range of offsets given to them.
+    fn append(&mut self, batch: Batch) -> Result<Range<usize>, Error>;
+
+    fn read(&self, offset: usize) -> Result<Message, Error>;
+
+    /// Returns a contiguous run of messages starting from the given offset, which stops
+    /// after max_messages or before the batches read from the log exceed max_bytes.
+    ///
+    /// At least one batch is read even if it is bigger than max_bytes,
+    /// and none if the offset is the end of the log.
+    fn read_range(
+        &self,
+        start: usize,
+        max_messages: usize,
+        max_bytes: usize,
+    ) -> Result<Vec<Message>, Error>;
+
+    /// Returns the offset of the first message with the timestamp at or after the given one,
+    /// or None if every message in the log is older than it.
+    fn offset_for_timestamp(&self, timestamp: DateTime<Utc>) -> Result<Option<usize>, Error>;
+
+    /// Removes the messages with the offsets at or after the given one from the log,
+    /// so that the next appended message gets this offset.
+    fn truncate_to(&mut self, offset: usize) -> Result<(), Error>;
+
+    /// Returns the offset of the first message that could be read from the log.
+    fn log_start_offset(&self) -> usize;
+
+    /// Returns the offset that will be given to the next appended message.
+    fn log_end_offset(&self) -> usize;
+}
+
+impl LogStorage for Partition {
+    fn append(&mut self, batch: Batch) -> Result<Range<usize>, Error> {
+        self.write_batch(batch)
+    }
+
+    fn read(&self, offset: usize) -> Result<Message, Error> {
+        Partition::read(self, offset)
+    }
+
+    fn read_range(
+        &self,
+        start: usize,
+        max_messages: usize,
+        max_bytes: usize,
+    ) -> Result<Vec<Message>, Error> {
+        Partition::read_range(self, start, max_messages, max_bytes)
+    }
+
+    fn offset_for_timestamp(&self, timestamp: DateTime<Utc>) -> Result<Option<usize>, Error> {
+        Partition::offset_for_timestamp(self, timestamp)
+    }
+
+    fn truncate_to(&mut self, offset: usize) -> Result<(), Error> {
+        Partition::truncate_to(self, offset)
+    }
+
+    fn log_start_offset(&self) -> usize {
+        Partition::log_start_offset(self)
+    }
+
+    fn log_end_offset(&self) -> usize {
+        Partition::log_end_offset(self)
+    }
+}
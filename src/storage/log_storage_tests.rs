@@ -0,0 +1,199 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+use super::{
+    async_partition::Batch,
+    error::StorageError,
+    log_storage::LogStorage,
+    memory::MemoryLog,
+    partition::{Partition, PartitionConfig},
+    simulated_vfs::SimulatedVfs,
+};
+
+// run_suite runs every conformance check against a new log made by the given function.
+fn run_suite<L: LogStorage>(new_log: impl Fn() -> L) {
+    appends_get_consecutive_offsets(&mut new_log());
+    offsets_outside_of_the_log_are_out_of_range(&mut new_log());
+    range_reads_stop_at_the_first_batch_over_the_limit(&mut new_log());
+    offset_for_timestamp_finds_the_first_newer_message(&mut new_log());
+    truncation_gives_the_offset_to_the_next_append(&mut new_log());
+    keys_values_and_headers_round_trip(&mut new_log());
+}
+
+#[test]
+fn memory_log_conforms() {
+    run_suite(MemoryLog::new);
+}
+
+#[test]
+fn partition_conforms() {
+    run_suite(|| {
+        let config = PartitionConfig {
+            retention_check_interval: None,
+            vfs: Arc::new(SimulatedVfs::new()),
+            ..Default::default()
+        };
+        Partition::new("data".to_string(), 0, config).unwrap()
+    });
+}
+
+fn timestamp(seconds: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(1_700_000_000, 0).unwrap() + Duration::seconds(seconds)
+}
+
+// batch returns the messages with the given value sizes, which are written a second apart
+// starting from the given second.
+fn batch(first_second: i64, sizes: &[usize]) -> Batch {
+    sizes
+        .iter()
+        .enumerate()
+        .map(|(i, &size)| {
+            (
+                timestamp(first_second + i as i64),
+                None,
+                vec![7; size],
+                vec![],
+            )
+        })
+        .collect()
+}
+
+fn offsets(
+    log: &impl LogStorage,
+    start: usize,
+    max_messages: usize,
+    max_bytes: usize,
+) -> Vec<usize> {
+    log.read_range(start, max_messages, max_bytes)
+        .unwrap()
+        .iter()
+        .map(|m| m.offset)
+        .collect()
+}
+
+fn assert_out_of_range(result: Result<impl std::fmt::Debug, std::io::Error>) {
+    let error = result.unwrap_err();
+    assert!(
+        matches!(
+            StorageError::of(&error),
+            Some(StorageError::OffsetOutOfRange { .. })
+        ),
+        "unexpected error: {}",
+        error
+    );
+}
+
+fn appends_get_consecutive_offsets(log: &mut impl LogStorage) {
+    assert_eq!(log.log_start_offset(), 0);
+    assert_eq!(log.log_end_offset(), 0);
+
+    assert_eq!(log.append(batch(0, &[1, 2, 3])).unwrap(), 0..3);
+    assert_eq!(log.append(batch(3, &[])).unwrap(), 3..3);
+    assert_eq!(log.append(batch(3, &[4])).unwrap(), 3..4);
+    assert_eq!(log.log_end_offset(), 4);
+
+    for offset in 0..4 {
+        let message = log.read(offset).unwrap();
+        assert_eq!(message.offset, offset);
+        assert_eq!(message.timestamp, timestamp(offset as i64));
+        assert_eq!(message.value, vec![7; offset + 1]);
+    }
+}
+
+fn offsets_outside_of_the_log_are_out_of_range(log: &mut impl LogStorage) {
+    log.append(batch(0, &[1, 1])).unwrap();
+
+    assert_out_of_range(log.read(2));
+    assert_out_of_range(log.read(10));
+    assert_out_of_range(log.read_range(3, 10, 1024));
+    assert_out_of_range(log.truncate_to(3));
+    assert!(log.read_range(2, 10, 1024).unwrap().is_empty());
+}
+
+fn range_reads_stop_at_the_first_batch_over_the_limit(log: &mut impl LogStorage) {
+    log.append(batch(0, &[10, 10])).unwrap();
+    log.append(batch(2, &[1000])).unwrap();
+    log.append(batch(3, &[10, 10])).unwrap();
+
+    assert_eq!(offsets(log, 0, 10, 500), vec![0, 1]);
+    assert_eq!(offsets(log, 1, 10, 500), vec![1]);
+    assert_eq!(offsets(log, 0, 3, 1 << 20), vec![0, 1, 2]);
+    assert_eq!(offsets(log, 0, 10, 1 << 20), vec![0, 1, 2, 3, 4]);
+
+    // the first batch is read even if it is over the limit.
+    assert_eq!(offsets(log, 2, 10, 1), vec![2]);
+    assert_eq!(offsets(log, 4, 10, 1), vec![4]);
+}
+
+fn offset_for_timestamp_finds_the_first_newer_message(log: &mut impl LogStorage) {
+    log.append(batch(0, &[1, 1])).unwrap();
+    log.append(batch(10, &[1])).unwrap();
+
+    assert_eq!(log.offset_for_timestamp(timestamp(-5)).unwrap(), Some(0));
+    assert_eq!(log.offset_for_timestamp(timestamp(1)).unwrap(), Some(1));
+    assert_eq!(log.offset_for_timestamp(timestamp(5)).unwrap(), Some(2));
+    assert_eq!(log.offset_for_timestamp(timestamp(11)).unwrap(), None);
+
+    // the timestamps, which could not be stored in nanoseconds, are still compared.
+    let past = Utc.with_ymd_and_hms(1500, 1, 1, 0, 0, 0).unwrap();
+    let future = Utc.with_ymd_and_hms(2300, 1, 1, 0, 0, 0).unwrap();
+    assert_eq!(log.offset_for_timestamp(past).unwrap(), Some(0));
+    assert_eq!(log.offset_for_timestamp(future).unwrap(), None);
+}
+
+fn truncation_gives_the_offset_to_the_next_append(log: &mut impl LogStorage) {
+    log.append(batch(0, &[1, 2, 3])).unwrap();
+    log.append(batch(3, &[4, 5])).unwrap();
+
+    log.truncate_to(5).unwrap();
+    assert_eq!(log.log_end_offset(), 5);
+
+    // the cut in the middle of a batch keeps the messages before it.
+    log.truncate_to(2).unwrap();
+    assert_eq!(log.log_end_offset(), 2);
+    assert_out_of_range(log.read(2));
+    assert_eq!(offsets(log, 0, 10, 1 << 20), vec![0, 1]);
+    assert_eq!(log.read(1).unwrap().value, vec![7; 2]);
+
+    assert_eq!(log.append(batch(20, &[9])).unwrap(), 2..3);
+    assert_eq!(log.read(2).unwrap().value, vec![7; 9]);
+    assert_eq!(log.offset_for_timestamp(timestamp(2)).unwrap(), Some(2));
+}
+
+fn keys_values_and_headers_round_trip(log: &mut impl LogStorage) {
+    // the empty keys, values, header names and header values are kept apart from no key
+    // or no headers, and the repeated header names keep their order.
+    let batch: Batch = vec![
+        (
+            timestamp(0),
+            Some(vec![]),
+            vec![],
+            vec![
+                ("trace".to_string(), b"1".to_vec()),
+                ("trace".to_string(), vec![]),
+                (String::new(), b"x".to_vec()),
+            ],
+        ),
+        (timestamp(1), None, b"value".to_vec(), vec![]),
+        (
+            timestamp(2),
+            Some(b"key".to_vec()),
+            vec![0; 300],
+            vec![("type".to_string(), vec![1; 300])],
+        ),
+    ];
+    log.append(batch.clone()).unwrap();
+
+    let ranged = log.read_range(0, 10, 1 << 20).unwrap();
+    assert_eq!(ranged.len(), batch.len());
+    for (offset, (timestamp, key, value, headers)) in batch.into_iter().enumerate() {
+        for message in [log.read(offset).unwrap(), ranged[offset].clone()] {
+            assert_eq!(message.offset, offset);
+            assert_eq!(message.timestamp, timestamp);
+            assert_eq!(message.key, key);
+            assert_eq!(message.value, value);
+            assert_eq!(message.headers, headers);
+        }
+    }
+}
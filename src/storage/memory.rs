@@ -0,0 +1,165 @@
+use std::{io::Error, ops::Range};
+
+use chrono::{DateTime, Utc};
+
+use crate::core::message::Message;
+
+use super::{
+    async_partition::Batch, compression::Compression, error::StorageError, format::BatchBuilder,
+    log_storage::LogStorage, segment::RangeRead,
+};
+
+/// MemoryLog is a LogStorage, which keeps the messages in the memory and loses them
+/// when it is dropped.
+///
+/// Every append is kept as one batch, and the size of a batch is the size it would have
+/// in a log without the compression. So the range reads are limited the same way as the ones
+/// of a partition without the compression, as long as every append fits into one of its
+/// batches, see LogStorage.
+#[derive(Debug, Default)]
+pub struct MemoryLog {
+    /// batches are the appended batches in the order of their offsets, none of them is empty.
+    batches: Vec<MemoryBatch>,
+    /// next_offset is an offset that will be given to the next appended message.
+    next_offset: usize,
+}
+
+// MemoryBatch is the messages of an append along with their size in a log.
+#[derive(Debug)]
+struct MemoryBatch {
+    messages: Vec<Message>,
+    size: usize,
+}
+
+impl MemoryLog {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // batch_position returns the index of the batch, which stores the given offset,
+    // or the amount of the batches if the offset is the end of the log.
+    fn batch_position(&self, offset: usize) -> usize {
+        self.batches
+            .partition_point(|b| b.messages.last().is_some_and(|m| m.offset < offset))
+    }
+
+    fn out_of_range(&self, offset: usize) -> Error {
+        StorageError::OffsetOutOfRange {
+            offset,
+            log_start_offset: 0,
+            log_end_offset: self.next_offset,
+        }
+        .into()
+    }
+
+    // encoded_size returns the size of the batch with the given messages in a log.
+    fn encoded_size(messages: &[Message]) -> Result<usize, Error> {
+        let mut builder = BatchBuilder::default();
+        for message in messages {
+            let record = builder.encode(message)?;
+            builder.push(message, &record)?;
+        }
+
+        let (_, batch) = builder.build(Compression::None)?;
+        Ok(batch.len())
+    }
+}
+
+impl LogStorage for MemoryLog {
+    /// The messages are validated before any of them is added, so a failed append
+    /// leaves the log as it was.
+    fn append(&mut self, batch: Batch) -> Result<Range<usize>, Error> {
+        let first_offset = self.next_offset;
+        let messages: Vec<Message> = batch
+            .into_iter()
+            .enumerate()
+            .map(|(i, (timestamp, key, value, headers))| {
+                Message::new(first_offset + i, timestamp, key, value, headers)
+            })
+            .collect();
+        if messages.is_empty() {
+            return Ok(first_offset..first_offset);
+        }
+
+        let size = Self::encoded_size(&messages)?;
+        self.next_offset += messages.len();
+        self.batches.push(MemoryBatch { messages, size });
+
+        Ok(first_offset..self.next_offset)
+    }
+
+    fn read(&self, offset: usize) -> Result<Message, Error> {
+        if offset >= self.next_offset {
+            return Err(self.out_of_range(offset));
+        }
+
+        let batch = &self.batches[self.batch_position(offset)];
+        let first = batch.messages[0].offset;
+        Ok(batch.messages[offset - first].clone())
+    }
+
+    fn read_range(
+        &self,
+        start: usize,
+        max_messages: usize,
+        max_bytes: usize,
+    ) -> Result<Vec<Message>, Error> {
+        if start > self.next_offset {
+            return Err(self.out_of_range(start));
+        }
+
+        let mut read = RangeRead::new(max_messages, max_bytes);
+        for batch in self.batches[self.batch_position(start)..].iter() {
+            if read.is_full() || !read.take_batch(batch.size) {
+                break;
+            }
+
+            for message in batch.messages.iter().filter(|m| m.offset >= start) {
+                if !read.take_message(message.clone()) {
+                    break;
+                }
+            }
+        }
+
+        Ok(read.messages)
+    }
+
+    fn offset_for_timestamp(&self, timestamp: DateTime<Utc>) -> Result<Option<usize>, Error> {
+        Ok(self
+            .batches
+            .iter()
+            .flat_map(|b| b.messages.iter())
+            .find(|m| m.timestamp >= timestamp)
+            .map(|m| m.offset))
+    }
+
+    fn truncate_to(&mut self, offset: usize) -> Result<(), Error> {
+        if offset > self.next_offset {
+            return Err(self.out_of_range(offset));
+        }
+
+        let position = self.batch_position(offset);
+        self.batches.truncate(position + 1);
+        if let Some(batch) = self.batches.get_mut(position) {
+            let first = batch.messages[0].offset;
+            batch.messages.truncate(offset - first);
+            match batch.messages.is_empty() {
+                true => {
+                    self.batches.pop();
+                }
+                false => batch.size = Self::encoded_size(&batch.messages)?,
+            }
+        }
+
+        self.next_offset = offset;
+        Ok(())
+    }
+
+    fn log_start_offset(&self) -> usize {
+        0
+    }
+
+    fn log_end_offset(&self) -> usize {
+        self.next_offset
+    }
+}
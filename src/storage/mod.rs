@@ -1,5 +1,28 @@
+pub mod async_partition;
+pub mod compression;
+pub mod error;
+pub mod format;
+pub mod iter;
+pub mod log_storage;
+pub mod memory;
 pub mod partition;
+pub mod region;
+pub mod remote;
+pub mod report;
+pub mod simulated_vfs;
+pub mod vfs;
 
+mod checkpoint;
+mod cleaner;
 mod offset_index;
 mod segment;
+mod tiered;
 mod timestamp_index;
+mod upgrade;
+
+#[cfg(test)]
+mod log_storage_tests;
+#[cfg(test)]
+mod partition_tests;
+#[cfg(test)]
+mod recovery_tests;
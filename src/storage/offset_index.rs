@@ -1,103 +1,213 @@
 use std::{
-    cell::RefCell,
-    fs::{self, File, OpenOptions},
-    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
-    os::unix::fs::MetadataExt,
+    io::{Error, ErrorKind},
+    sync::Arc,
 };
 
-use serde::{Deserialize, Serialize};
+use super::{
+    format::{self, FileKind, FILE_HEADER_SIZE, INDEX_ENTRY_SIZE},
+    vfs::{Vfs, VfsFile},
+};
 
+/// OffsetIndex is a sparse index from the base offsets of the batches to their physical positions
+/// in the log.
+///
+/// Not every batch gets an entry, the segment decides how often to add one. All entries are
+/// kept in memory in the ascending order, so a lookup is a binary search for the closest entry
+/// that is not greater than the requested offset; the rest is a short forward scan of the log.
 pub struct OffsetIndex {
-    file: RefCell<File>,
-    path: String,
+    file: Arc<dyn VfsFile>,
+    entries: Vec<Index>,
+    /// detached tells that a write to the file failed, so the file is behind the entries
+    /// and is not written to or synced anymore.
+    detached: bool,
 }
 
 impl OffsetIndex {
-    pub fn new(path: String) -> Result<Self, Error> {
-        match OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(path.clone())
-        {
-            Ok(file) => Ok(Self {
-                file: RefCell::new(file),
-                path,
-            }),
-            Err(e) => Err(e),
+    pub fn new(vfs: &dyn Vfs, path: String) -> Result<Self, Error> {
+        let file = vfs.open(&path)?;
+        format::version_guard(&*file, FileKind::OffsetIndex, &path)?;
+
+        let entries = Self::read_entries(&*file)?;
+
+        Ok(Self {
+            file,
+            entries,
+            detached: false,
+        })
+    }
+
+    /// Appends the given (logical, physical) entries to the index with a single write.
+    ///
+    /// The entries are added even if the write fails, then the index is detached from its file.
+    pub fn append(&mut self, entries: &[(usize, usize)]) -> Result<(), Error> {
+        let mut data = Vec::with_capacity(entries.len() * INDEX_ENTRY_SIZE);
+        let mut last = self.entries.last().map(|e| (e.logical, e.physical));
+
+        for &(logical, physical) in entries {
+            if let Some((last_logical, last_physical)) = last {
+                if last_logical >= logical || last_physical >= physical {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "index entry ({}, {}) is not after the last one ({}, {})",
+                            logical, physical, last_logical, last_physical
+                        ),
+                    ));
+                }
+            }
+
+            data.extend(Index::serialize(&Index { logical, physical }));
+            last = Some((logical, physical));
         }
+
+        if data.is_empty() {
+            return Ok(());
+        }
+
+        // the entries are written right after the last one, over a torn entry if there is one.
+        let position = FILE_HEADER_SIZE + self.entries.len() * INDEX_ENTRY_SIZE;
+        self.entries.extend(
+            entries
+                .iter()
+                .map(|&(logical, physical)| Index { logical, physical }),
+        );
+        self.write_entries(&data, position)
     }
 
-    pub fn write(&mut self, logical: usize, physical: usize) -> Result<(), Error> {
-        let data = Index::serialize(Index { logical, physical })?;
+    /// Replaces the content of the index with the given (logical, physical) entries.
+    /// Returns false and does nothing if the index already contains exactly these entries.
+    pub fn rebuild(&mut self, entries: &[(usize, usize)]) -> Result<bool, Error> {
+        let entries: Vec<Index> = entries
+            .iter()
+            .map(|&(logical, physical)| Index { logical, physical })
+            .collect();
+        if entries == self.entries {
+            return Ok(false);
+        }
+
+        let mut data = Vec::with_capacity(entries.len() * INDEX_ENTRY_SIZE);
+        for index in entries.iter() {
+            data.extend(Index::serialize(index));
+        }
 
-        let mut file = self.file.borrow_mut();
+        format::write_file_header(&*self.file, FileKind::OffsetIndex)?;
+        self.file.write_all_at(&data, FILE_HEADER_SIZE as u64)?;
+        // the stale entries after the rebuilt ones are cut off.
+        self.file.set_len((FILE_HEADER_SIZE + data.len()) as u64)?;
 
-        file.seek(SeekFrom::End(0))?;
-        file.write_all(&data)
+        self.entries = entries;
+        Ok(true)
     }
 
-    pub fn read(&self, logical: usize) -> Result<usize, Error> {
-        let mut buffer = vec![0; Index::size()];
+    /// Removes the entries, which point at the given physical position or after it.
+    pub fn truncate(&mut self, physical: usize) -> Result<(), Error> {
+        let keep = self.entries.partition_point(|e| e.physical < physical);
+        if keep == self.entries.len() {
+            return Ok(());
+        }
 
-        self.file.borrow_mut().seek(SeekFrom::Start(0))?;
-        loop {
-            let index = self.read_index(Some(&mut buffer))?;
-            if index.logical == logical {
-                return Ok(index.physical);
-            }
+        if !self.detached {
+            self.file
+                .set_len((FILE_HEADER_SIZE + keep * INDEX_ENTRY_SIZE) as u64)?;
         }
+        self.entries.truncate(keep);
+        Ok(())
     }
 
-    pub fn size(&self) -> Result<usize, Error> {
-        let file_size = fs::metadata(self.path.clone())?.size() as usize;
-        Ok(file_size / Index::size())
+    /// Returns the physical position from which the log should be scanned to find the batch
+    /// with the given logical offset, that is the position of the closest indexed batch
+    /// that does not start after it, or the beginning of the log if there is no such entry.
+    pub fn lookup(&self, logical: usize) -> usize {
+        let idx = self.entries.partition_point(|e| e.logical <= logical);
+        match idx {
+            0 => FILE_HEADER_SIZE,
+            _ => self.entries[idx - 1].physical,
+        }
     }
 
-    fn read_index(&self, buffer: Option<&mut [u8]>) -> Result<Index, Error> {
-        let mut owned_buffer;
-        let buffer = match buffer {
-            Some(buf) => buf,
-            None => {
-                owned_buffer = vec![0u8; Index::size()];
-                owned_buffer.as_mut_slice()
-            }
-        };
-
-        let bytes_read = self.file.borrow_mut().read(buffer)?;
-        if bytes_read != Index::size() {
-            return Err(Error::new(
-                ErrorKind::NotFound,
-                "index not found in the file",
-            ));
+    /// Tells whether every entry points into a log of the given size, in the ascending order.
+    pub fn fits_log(&self, log_size: usize) -> bool {
+        self.entries
+            .iter()
+            .all(|e| e.physical >= FILE_HEADER_SIZE && e.physical < log_size)
+            && self
+                .entries
+                .windows(2)
+                .all(|w| w[0].logical < w[1].logical && w[0].physical < w[1].physical)
+    }
+
+    /// Returns the last (logical, physical) pair stored in the index.
+    pub fn last(&self) -> Option<(usize, usize)> {
+        self.entries.last().map(|e| (e.logical, e.physical))
+    }
+
+    /// Writes all of the entries to a new file at the given path and syncs it, if the index
+    /// is detached from its file, and tells whether it was.
+    pub fn reattach(&mut self, vfs: &dyn Vfs, path: &str) -> Result<bool, Error> {
+        if !self.detached {
+            return Ok(false);
+        }
+
+        let mut data = Vec::with_capacity(self.entries.len() * INDEX_ENTRY_SIZE);
+        for index in self.entries.iter() {
+            data.extend(Index::serialize(index));
         }
 
-        Index::deserialize(buffer)
+        let file = vfs.open(path)?;
+        format::write_file_header(&*file, FileKind::OffsetIndex)?;
+        file.write_all_at(&data, FILE_HEADER_SIZE as u64)?;
+        file.set_len((FILE_HEADER_SIZE + data.len()) as u64)?;
+        file.sync_data()?;
+
+        self.file = file;
+        self.detached = false;
+        Ok(true)
+    }
+
+    pub fn sync(&self) -> Result<(), Error> {
+        match self.detached {
+            true => Ok(()),
+            false => self.file.sync_data(),
+        }
+    }
+
+    // write_entries writes the serialized entries to the file at the given position,
+    // unless the index is detached, and detaches it if the write fails.
+    fn write_entries(&mut self, data: &[u8], position: usize) -> Result<(), Error> {
+        if self.detached {
+            return Ok(());
+        }
+
+        let result = self.file.write_all_at(data, position as u64);
+        self.detached = result.is_err();
+        result
+    }
+
+    fn read_entries(file: &dyn VfsFile) -> Result<Vec<Index>, Error> {
+        let size = file.size()? as usize;
+        let mut buffer = vec![0u8; size.saturating_sub(FILE_HEADER_SIZE)];
+        file.read_exact_at(&mut buffer, FILE_HEADER_SIZE as u64)?;
+
+        buffer
+            .chunks_exact(INDEX_ENTRY_SIZE)
+            .map(Index::deserialize)
+            .collect()
     }
 }
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(PartialEq)]
 struct Index {
     logical: usize,
     physical: usize,
 }
 
 impl Index {
-    pub fn size() -> usize {
-        let index = Index::default();
-
-        bincode::serialize(&index).unwrap().len()
-    }
-
     pub fn deserialize(buffer: &[u8]) -> Result<Self, Error> {
-        bincode::deserialize(buffer).map_err(Self::error)
-    }
-
-    pub fn serialize(index: Index) -> Result<Vec<u8>, Error> {
-        bincode::serialize(&index).map_err(Self::error)
+        let (logical, physical) = format::decode_offset_entry(buffer)?;
+        Ok(Self { logical, physical })
     }
 
-    fn error(e: bincode::Error) -> Error {
-        Error::new(ErrorKind::InvalidData, e)
+    pub fn serialize(index: &Index) -> [u8; INDEX_ENTRY_SIZE] {
+        format::encode_offset_entry(index.logical, index.physical)
     }
 }
@@ -1,16 +1,187 @@
 use core::fmt;
 use std::{
-    fs,
+    cmp,
+    collections::{BTreeMap, BTreeSet, HashSet},
     io::{Error, ErrorKind},
+    ops::{Bound, Range},
     path::Path,
-    sync::{Arc, Mutex, RwLock},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock, Weak,
+    },
+    time::Duration,
 };
 
 use chrono::{DateTime, Utc};
+use tokio::sync::watch;
 
-use crate::core::message::{Message, RawData};
+use crate::core::message::{Headers, Message, RawData};
 
-use super::segment::Segment;
+use super::{
+    checkpoint,
+    cleaner::Cleaner,
+    compression::{Compression, CompressionStats},
+    error::StorageError,
+    format::{self, BATCH_HEADER_SIZE, FILE_HEADER_SIZE},
+    iter::{PartitionIter, Truncations},
+    region::FileRegion,
+    remote::RemoteStorage,
+    report::LoadReport,
+    segment::{RangeRead, Segment, SegmentLimits, LOG_EXTENSION, SEGMENT_EXTENSIONS},
+    tiered::RemoteTier,
+    upgrade,
+    vfs::{RealVfs, Vfs},
+};
+
+/// TRUNCATE_DIR is the directory inside of a partition, where the truncated copy of a segment
+/// is written before it replaces the original one.
+const TRUNCATE_DIR: &str = "truncate";
+
+/// DurabilityPolicy defines when the written messages are synced to the disk.
+///
+/// With any policy but OsManaged the directory of the partition is also synced every time
+/// the segment files are created, renamed or removed.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DurabilityPolicy {
+    /// Every write is synced before it returns.
+    Always,
+    /// The log is synced after every N written messages.
+    EveryMessages(usize),
+    /// The log is synced by a background task with the given interval,
+    /// the partition has to be created inside of a tokio runtime.
+    Interval(Duration),
+    /// Syncing is left to the operating system.
+    OsManaged,
+}
+
+/// CleanupPolicy defines how the old messages are removed from a partition.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CleanupPolicy {
+    /// Whole segments are deleted according to the retention limits.
+    Delete,
+    /// Closed segments are compacted, so that only the last message for each key is left.
+    Compact,
+}
+
+/// PartitionConfig holds the settings of a partition.
+#[derive(Debug, Clone)]
+pub struct PartitionConfig {
+    /// max_segment_messages is the max amount of messages that could be stored in one segment.
+    pub max_segment_messages: usize,
+    /// max_segment_bytes is the max size of the segment's log in bytes.
+    pub max_segment_bytes: usize,
+    /// max_segment_age is the max difference between the timestamps of the first message
+    /// in a segment and a new one, after which a new segment is rolled.
+    pub max_segment_age: Option<Duration>,
+    /// durability defines when the written messages are synced to the disk.
+    pub durability: DurabilityPolicy,
+    /// compression is the codec, with which the messages are compressed by default.
+    pub compression: Compression,
+    /// retention_age is the max age of the newest message in a closed segment,
+    /// after which the segment is deleted.
+    pub retention_age: Option<Duration>,
+    /// retention_bytes is the max total size of the logs in the partition,
+    /// the oldest closed segments are deleted to fit into it.
+    pub retention_bytes: Option<usize>,
+    /// cleanup_policy defines how the old messages are removed from the partition.
+    pub cleanup_policy: CleanupPolicy,
+    /// tombstone_retention is the time, after which a message with an empty value is removed
+    /// from a compacted partition.
+    pub tombstone_retention: Duration,
+    /// retention_check_interval is the interval, with which a background task enforces
+    /// the retention if any of the limits is set, and compacts the partition if the cleanup
+    /// policy says so. The partition then has to be created inside of a tokio runtime.
+    /// Without it this is done only by Partition::enforce_retention and Partition::compact.
+    pub retention_check_interval: Option<Duration>,
+    /// remote_storage is the secondary store, to which the closed segments are copied by the
+    /// retention task or by Partition::offload_segments. The segments are read from it after
+    /// their local copies are deleted. It could not be used along with the compaction.
+    pub remote_storage: Option<Arc<dyn RemoteStorage>>,
+    /// local_retention_age is the max age of the newest message in a closed segment copied
+    /// to the remote storage, after which its local copy is deleted.
+    pub local_retention_age: Option<Duration>,
+    /// remote_cache_segments is the max amount of the segments fetched from the remote storage,
+    /// which are kept on the local disk.
+    pub remote_cache_segments: usize,
+    /// vfs is the file system, on which the files of the partition are stored,
+    /// along with the segments fetched from the remote storage.
+    pub vfs: Arc<dyn Vfs>,
+}
+
+impl Default for PartitionConfig {
+    fn default() -> Self {
+        Self {
+            max_segment_messages: 1_000_000,
+            max_segment_bytes: 1 << 30,
+            max_segment_age: None,
+            durability: DurabilityPolicy::OsManaged,
+            compression: Compression::None,
+            retention_age: None,
+            retention_bytes: None,
+            cleanup_policy: CleanupPolicy::Delete,
+            tombstone_retention: Duration::from_secs(24 * 60 * 60),
+            retention_check_interval: Some(Duration::from_secs(300)),
+            remote_storage: None,
+            local_retention_age: None,
+            remote_cache_segments: 4,
+            vfs: Arc::new(RealVfs),
+        }
+    }
+}
+
+impl PartitionConfig {
+    /// Checks that the settings could be used together and that a segment could take
+    /// at least one message.
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.max_segment_messages == 0 {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "max_segment_messages must be at least 1",
+            ));
+        }
+        if self.max_segment_bytes < FILE_HEADER_SIZE + BATCH_HEADER_SIZE {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "max_segment_bytes must be at least {}",
+                    FILE_HEADER_SIZE + BATCH_HEADER_SIZE
+                ),
+            ));
+        }
+        if self.remote_storage.is_some() && self.cleanup_policy == CleanupPolicy::Compact {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "compacted partitions could not be copied to a remote storage",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Syncs the directory of the partition after the segment files in it were created,
+    /// renamed or removed, so that the change survives a crash, unless the durability
+    /// is left to the OS.
+    pub fn sync_dir(&self, path: &str) -> Result<(), Error> {
+        match self.durability {
+            DurabilityPolicy::OsManaged => Ok(()),
+            _ => self.vfs.sync_dir(path),
+        }
+    }
+
+    fn segment_limits(&self) -> SegmentLimits {
+        SegmentLimits {
+            max_messages: self.max_segment_messages,
+            max_bytes: self.max_segment_bytes,
+            max_age: self.max_segment_age,
+        }
+    }
+}
+
+/// Segments of a partition by their base offsets.
+///
+/// Every segment has its own lock, which is taken for writing only to append to it,
+/// so the reads of a segment run concurrently with each other.
+pub type Segments = RwLock<BTreeMap<usize, Arc<RwLock<Segment>>>>;
 
 /// Partition is an immutable log of messages.
 pub struct Partition {
@@ -18,125 +189,1152 @@ pub struct Partition {
     number: usize,
     /// base_path is the path, where this partition stores data.
     path: String,
-    /// segment_size is the max amount of messages that could be stored in one segment.
-    segment_size: usize,
-    /// next_offset is an offset that will be given to the next created message in this log.
-    next_offset: usize,
+    /// config holds the settings of this partition.
+    config: PartitionConfig,
+    /// next_offset is an offset that will be given to the next created message in this log,
+    /// it is changed only with the writer locked.
+    next_offset: AtomicUsize,
+    /// writer is locked by the writes and the truncations of the log, so they need only
+    /// a shared reference and the partition could be read while it is written.
+    writer: Mutex<Writer>,
+    /// synced_offset is the offset, before which all of the messages are synced to the disk.
+    synced_offset: Arc<AtomicUsize>,
+    /// log_start_offset is the offset of the first message that could be read from this log.
+    log_start_offset: Arc<AtomicUsize>,
+    /// end_offset announces the end of the log to the iterators after every write.
+    end_offset: watch::Sender<usize>,
+    /// truncations tells the iterators, to which offsets the log was truncated,
+    /// so their positions in the segments could be invalid.
+    truncations: Arc<Truncations>,
+
+    segments: Arc<Segments>,
+    /// tier holds the segments copied to the remote storage, if there is one in the config.
+    tier: Option<Arc<RemoteTier>>,
+    /// load_report tells what was upgraded and repaired in the segments on load.
+    load_report: LoadReport,
+    /// background_error is the last error of the background tasks, which is not taken yet.
+    background_error: Arc<Mutex<Option<Error>>>,
+    /// compaction is held while the segments are compacted, since the compactions share
+    /// the cleaner's directory and could not run concurrently.
+    compaction: Arc<Mutex<()>>,
+}
 
-    segments: Arc<RwLock<Vec<Arc<Mutex<Segment>>>>>,
+// Writer is the state of the partition, which is changed only by the writes.
+#[derive(Default)]
+struct Writer {
+    /// unsynced is the amount of messages written after the last sync.
+    unsynced: usize,
+    /// compression_stats tells how much the messages written since the partition
+    /// was opened were compressed.
+    compression_stats: CompressionStats,
 }
 
 impl Partition {
-    pub fn new(path: String, number: usize, segment_size: usize) -> Result<Self, Error> {
+    pub fn new(path: String, number: usize, config: PartitionConfig) -> Result<Self, Error> {
+        config.validate()?;
+
         let dir_path = format!("{}/{:08}", &path, number);
-        match Path::new(&dir_path).exists() {
-            true => Self::load(format!("{}/", dir_path), number, segment_size),
-            false => Self::init(dir_path, number, segment_size),
+        let exists = config.vfs.exists(&dir_path);
+        let tier = match &config.remote_storage {
+            Some(storage) => Some(Arc::new(RemoteTier::open(
+                storage.clone(),
+                config.vfs.clone(),
+                &dir_path,
+                number,
+                config.remote_cache_segments,
+            )?)),
+            None => None,
+        };
+
+        let partition = match exists {
+            true => Self::load(format!("{}/", dir_path), number, config, tier),
+            false => Self::init(dir_path, number, config, tier),
+        }?;
+
+        if let DurabilityPolicy::Interval(interval) = partition.config.durability {
+            partition.start_sync_task(interval)?;
         }
+        if let Some(interval) = partition.config.retention_check_interval {
+            if partition.config.retention_age.is_some()
+                || partition.config.retention_bytes.is_some()
+                || partition.config.cleanup_policy == CleanupPolicy::Compact
+                || partition.tier.is_some()
+            {
+                partition.start_retention_task(interval)?;
+            }
+        }
+
+        Ok(partition)
     }
 
     pub fn write(
-        &mut self,
+        &self,
         timestamp: DateTime<Utc>,
         key: Option<RawData>,
         value: RawData,
+        headers: Headers,
     ) -> Result<(), Error> {
+        self.write_batch(vec![(timestamp, key, value, headers)])?;
+        Ok(())
+    }
+
+    /// Appends the messages to the log and returns the range of offsets given to them.
+    ///
+    /// Only the active segment is locked for the write, so the other segments could be read
+    /// at the same time, and the list of the segments is locked only to add a new one.
+    /// The messages that fit into the active segment are written to it at once, the rest go
+    /// to the new segments, which are rolled when the active one reaches any of the limits
+    /// from the config. The timestamps of all the messages are checked before any of them
+    /// is written, but if a write fails, the messages before it stay in the log.
+    pub fn write_batch(
+        &self,
+        batch: Vec<(DateTime<Utc>, Option<RawData>, RawData, Headers)>,
+    ) -> Result<Range<usize>, Error> {
+        self.write_batch_compressed(batch, self.config.compression)
+    }
+
+    /// Same as Partition::write_batch, but the messages are compressed with the given codec
+    /// instead of the one from the config.
+    pub fn write_batch_compressed(
+        &self,
+        batch: Vec<(DateTime<Utc>, Option<RawData>, RawData, Headers)>,
+        compression: Compression,
+    ) -> Result<Range<usize>, Error> {
+        let mut writer = self.writer.lock().unwrap();
+        let first_offset = self.log_end_offset();
+        let mut next_offset = first_offset;
+        let messages: Vec<Message> = batch
+            .into_iter()
+            .enumerate()
+            .map(|(i, (timestamp, key, value, headers))| {
+                Message::new(first_offset + i, timestamp, key, value, headers)
+            })
+            .collect();
+        // the batch is rejected whole, before any of its messages is written.
+        format::validate_timestamps(&messages)?;
+        let mut messages = messages.into_iter().peekable();
+
+        let limits = self.config.segment_limits();
+
+        while messages.peek().is_some() {
+            let active = self.active_segment();
+            let written = match &active {
+                Some(segment) => {
+                    let batch = segment.write().unwrap().write_batch(
+                        &mut messages,
+                        &limits,
+                        compression,
+                    )?;
+                    writer.compression_stats += batch.compression;
+                    batch.messages
+                }
+                None => 0,
+            };
+            next_offset += written;
+            writer.unsynced += written;
+            if written > 0 {
+                self.next_offset.store(next_offset, Ordering::Release);
+                self.end_offset.send_replace(next_offset);
+            }
+
+            if written == 0 {
+                if let Some(segment) = &active {
+                    self.sync_rolled_segment(&segment.read().unwrap())?;
+                }
+
+                let segment =
+                    Segment::new(self.config.vfs.clone(), self.path.clone(), next_offset)?;
+                self.config.sync_dir(&self.path)?;
+                self.segments
+                    .write()
+                    .unwrap()
+                    .insert(next_offset, Arc::new(RwLock::new(segment)));
+            }
+        }
+
+        let sync_needed = match self.config.durability {
+            DurabilityPolicy::Always => true,
+            DurabilityPolicy::EveryMessages(n) => writer.unsynced >= n,
+            DurabilityPolicy::Interval(_) | DurabilityPolicy::OsManaged => false,
+        };
+        if sync_needed {
+            if let Some(segment) = self.active_segment() {
+                segment.read().unwrap().sync()?;
+            }
+            writer.unsynced = 0;
+            self.synced_offset.store(next_offset, Ordering::Release);
+        }
+
+        Ok(first_offset..next_offset)
+    }
+
+    /// Removes the messages with the offsets at or after the given one from the log,
+    /// so that the next written message gets this offset.
+    ///
+    /// The later segments are deleted whole from the last one to the first, so an interrupted
+    /// truncation leaves a shorter, but continuous log, and only then the segment with
+    /// the offset is truncated. If the offset is at the start of a batch, the segment is
+    /// truncated in place by Segment::truncate_to. Otherwise, its truncated copy is written
+    /// by Segment::write_truncated_copy and replaces it, so the messages before the offset
+    /// from the same batch survive an interrupted truncation. The iterators, which are past
+    /// the new end of the log, continue from it. The offsets, which are stored only in
+    /// the remote storage, could not be truncated to.
+    pub fn truncate_to(&self, offset: usize) -> Result<(), Error> {
+        let _writer = self.writer.lock().unwrap();
+        let log_start_offset = self.log_start_offset();
+        let log_end_offset = self.log_end_offset();
+        if offset < log_start_offset || offset > log_end_offset {
+            return Err(StorageError::OffsetOutOfRange {
+                offset,
+                log_start_offset,
+                log_end_offset,
+            }
+            .into());
+        }
+        if offset == log_end_offset {
+            return Ok(());
+        }
+
+        Self::truncation_guard(&self.segments.read().unwrap(), offset)?;
+        self.truncations.record(offset);
+
+        // the remote copies are deleted first, so that the truncated messages are not read
+        // from them after the local copies are deleted, and without the segments locked,
+        // since it could be slow.
+        if let Some(tier) = &self.tier {
+            tier.delete_from(offset)?;
+        }
+
         let mut segments = self.segments.write().unwrap();
+        let (base_offset, segment) = Self::truncation_guard(&segments, offset)?;
 
-        let message = Message::new(self.next_offset, timestamp, key, value);
+        let later: Vec<usize> = segments
+            .range((Bound::Excluded(offset), Bound::Unbounded))
+            .map(|(&base_offset, _)| base_offset)
+            .collect();
+        for base_offset in later.iter().rev() {
+            segments[base_offset].read().unwrap().delete()?;
+            segments.remove(base_offset);
+        }
+        self.config.sync_dir(&self.path)?;
 
-        if let Some(segment) = segments.last_mut() {
-            let mut segment = segment.lock().unwrap();
-            if segment.size()? < self.segment_size {
-                println!("Existing segment :{}", segment);
+        let truncate_path = format!("{}/{}", self.path, TRUNCATE_DIR);
+        self.remove_dir(&truncate_path)?;
+        self.config.vfs.create_dir_all(&truncate_path)?;
 
-                segment.write(message)?;
-                self.next_offset += 1;
-                return Ok(());
+        let mut segment = segment.write().unwrap();
+        match segment.write_truncated_copy(offset, &truncate_path)? {
+            true => {
+                Segment::replace_files(
+                    self.config.vfs.as_ref(),
+                    &truncate_path,
+                    &self.path,
+                    base_offset,
+                )?;
+                self.config.sync_dir(&self.path)?;
             }
+            false => segment.truncate_to(offset)?,
         }
+        self.remove_dir(&truncate_path)?;
 
-        let mut segment = Segment::new(
-            self.path.clone(),
-            segments.len() as i32,
-            Self::segment_size(segments.len(), self.segment_size),
-        )?;
+        // the segment is reopened, so that the compaction that cleaned it before
+        // the truncation does not replace it.
+        let segment = Segment::new(self.config.vfs.clone(), self.path.clone(), base_offset)?;
+        segments.insert(base_offset, Arc::new(RwLock::new(segment)));
 
-        segment.write(message)?;
+        self.next_offset.store(offset, Ordering::Release);
+        self.synced_offset.fetch_min(offset, Ordering::AcqRel);
+        self.end_offset.send_replace(offset);
 
-        segments.push(Arc::new(Mutex::new(segment)));
+        Ok(())
+    }
+
+    // truncation_guard returns the local segment with the given offset along with its base offset,
+    // if it could be truncated.
+    fn truncation_guard(
+        segments: &BTreeMap<usize, Arc<RwLock<Segment>>>,
+        offset: usize,
+    ) -> Result<(usize, Arc<RwLock<Segment>>), Error> {
+        let Some((&base_offset, segment)) = segments.range(..=offset).next_back() else {
+            return Err(Error::new(
+                ErrorKind::Unsupported,
+                format!(
+                    "truncation to offset {} is not supported on offloaded segments",
+                    offset
+                ),
+            ));
+        };
+        if segment.read().unwrap().has_regions() {
+            return Err(Error::new(
+                ErrorKind::ResourceBusy,
+                format!("regions of the segment with offset {} are in use", offset),
+            ));
+        }
+
+        Ok((base_offset, segment.clone()))
+    }
+
+    /// Deletes the messages before the given offset and returns the amount of deleted segments.
+    ///
+    /// The new log start offset is saved to a checkpoint before it is applied, so the messages
+    /// before it are unreadable right away and stay so after a restart. Only the closed segments
+    /// that are entirely before the offset are removed from the disk, the rest of the messages
+    /// are removed along with their segments later by the retention. Raw reads return whole
+    /// batches, so they could still return the messages before the offset from its batch.
+    pub fn delete_records_before(&self, offset: usize) -> Result<usize, Error> {
+        let _writer = self.writer.lock().unwrap();
+        let log_start_offset = self.log_start_offset();
+        let log_end_offset = self.log_end_offset();
+        if offset > log_end_offset {
+            return Err(StorageError::OffsetOutOfRange {
+                offset,
+                log_start_offset,
+                log_end_offset,
+            }
+            .into());
+        }
+        if offset <= log_start_offset {
+            return Ok(0);
+        }
+
+        checkpoint::write_log_start_offset(self.config.vfs.as_ref(), &self.path, offset)?;
+        self.log_start_offset.fetch_max(offset, Ordering::AcqRel);
+
+        let mut segments = self.segments.write().unwrap();
+
+        // the active segment is never deleted, even if all of its messages are before the offset.
+        let closed = segments.len().saturating_sub(1);
+        let deleted: Vec<usize> = segments
+            .iter()
+            .take(closed)
+            .take_while(|(_, s)| s.read().unwrap().next_offset() <= offset)
+            .map(|(&base_offset, _)| base_offset)
+            .collect();
+
+        for base_offset in deleted.iter() {
+            if let Some(segment) = segments.remove(base_offset) {
+                segment.read().unwrap().delete()?;
+            }
+        }
+        if !deleted.is_empty() {
+            self.config.sync_dir(&self.path)?;
+        }
+
+        let mut deleted = BTreeSet::from_iter(deleted);
+        if let Some(tier) = &self.tier {
+            deleted.extend(tier.delete_before(offset)?);
+        }
+
+        Ok(deleted.len())
+    }
+
+    /// Returns what was upgraded and repaired in the segments, when the partition was loaded.
+    pub fn load_report(&self) -> &LoadReport {
+        &self.load_report
+    }
+
+    /// Returns the last error of the background tasks, which sync the partition and enforce
+    /// the retention, if there was one since the last call.
+    pub fn take_background_error(&self) -> Option<Error> {
+        self.background_error.lock().unwrap().take()
+    }
+
+    /// Returns how much the messages written since the partition was opened were compressed.
+    pub fn compression_stats(&self) -> CompressionStats {
+        self.writer.lock().unwrap().compression_stats
+    }
+
+    /// Returns the last offset that is synced to the disk,
+    /// or None if none of the messages is known to be synced.
+    pub fn last_synced_offset(&self) -> Option<usize> {
+        self.synced_offset.load(Ordering::Acquire).checked_sub(1)
+    }
+
+    /// Syncs every segment with the messages that are not synced yet to the disk,
+    /// regardless of the durability policy.
+    ///
+    /// Only a shared reference is needed, so the partition could be read while it is synced.
+    pub fn sync(&self) -> Result<(), Error> {
+        // the messages written while the segments are synced are not counted as synced.
+        let log_end_offset = self.log_end_offset();
+        let segments = self.segments.read().unwrap();
+        let synced_offset = self.synced_offset.load(Ordering::Acquire);
 
-        self.next_offset += 1;
+        for segment in segments.values() {
+            let segment = segment.read().unwrap();
+            if segment.next_offset() > synced_offset {
+                segment.sync()?;
+            }
+        }
+
+        self.synced_offset
+            .fetch_max(log_end_offset, Ordering::AcqRel);
         Ok(())
     }
 
     pub fn read(&self, offset: usize) -> Result<Message, Error> {
+        let log_start_offset = self.log_start_offset();
+        let log_end_offset = self.log_end_offset();
+        if offset < log_start_offset || offset >= log_end_offset {
+            return Err(StorageError::OffsetOutOfRange {
+                offset,
+                log_start_offset,
+                log_end_offset,
+            }
+            .into());
+        }
+
+        if let Some(segment) = self.remote_segment(offset)? {
+            return segment.read().unwrap().read(offset);
+        }
+
         let segments = self.segments.read().unwrap();
 
-        for s in segments.iter() {
-            let s = s.clone();
-            let s = s.lock().unwrap();
+        // the message could only be in the last segment that starts at or before its offset.
+        if let Some((_, s)) = segments.range(..=offset).next_back() {
+            let s = s.read().unwrap();
 
             if s.belongs_to_segment(offset) {
                 return s.read(offset);
             }
         }
 
+        // the segment could have been deleted by the retention after the offset was checked.
+        if offset < self.log_start_offset() {
+            return Err(StorageError::OffsetOutOfRange {
+                offset,
+                log_start_offset: self.log_start_offset(),
+                log_end_offset: self.log_end_offset(),
+            }
+            .into());
+        }
+
         Err(Error::new(
             ErrorKind::InvalidInput,
             "no message with this offset",
         ))
     }
 
-    fn init(path: String, number: usize, segment_size: usize) -> Result<Self, Error> {
-        fs::create_dir_all(&path)?;
+    /// Returns a contiguous run of messages starting from the given offset, which stops
+    /// after max_messages or before the batches read from the log exceed max_bytes.
+    ///
+    /// At least one batch is read even if it is bigger than max_bytes,
+    /// and none if the offset is the end of the log.
+    pub fn read_range(
+        &self,
+        start: usize,
+        max_messages: usize,
+        max_bytes: usize,
+    ) -> Result<Vec<Message>, Error> {
+        self.range_guard(start)?;
+
+        let mut read = RangeRead::new(max_messages, max_bytes);
+        if let Some(tier) = &self.tier {
+            tier.read_range(start, self.local_start_offset(), &mut read)?;
+        }
+
+        let segments = self.segments.read().unwrap();
+        // the log start offset is moved before the segments are deleted by the retention,
+        // so the segments from the start are still there, if it is still in the log.
+        self.range_guard(start)?;
+        for s in Self::segments_from(&segments, start) {
+            if read.is_full() {
+                break;
+            }
+            s.read().unwrap().read_range(start, &mut read)?;
+        }
+
+        Ok(read.messages)
+    }
+
+    /// Same as Partition::read_range, but returns the regions of the logs with the batches
+    /// as they are stored on the disk, one per segment, instead of decoding them.
+    ///
+    /// The batches are taken whole, so the first one could start before the given offset.
+    pub fn read_range_raw(
+        &self,
+        start: usize,
+        max_messages: usize,
+        max_bytes: usize,
+    ) -> Result<Vec<FileRegion>, Error> {
+        self.range_guard(start)?;
+
+        let mut read = RangeRead::new(max_messages, max_bytes);
+        let mut regions = Vec::new();
+        if let Some(tier) = &self.tier {
+            tier.read_range_raw(start, self.local_start_offset(), &mut read, &mut regions)?;
+        }
+
+        let segments = self.segments.read().unwrap();
+        // the log start offset is moved before the segments are deleted by the retention,
+        // so the segments from the start are still there, if it is still in the log.
+        self.range_guard(start)?;
+        for s in Self::segments_from(&segments, start) {
+            if read.is_full() {
+                break;
+            }
+            if let Some(region) = s.read().unwrap().read_range_raw(start, &mut read)? {
+                regions.push(region);
+            }
+        }
+
+        Ok(regions)
+    }
+
+    fn remove_dir(&self, path: &str) -> Result<(), Error> {
+        match self.config.vfs.remove_dir_all(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    // active_segment returns the last segment, to which the messages are written.
+    fn active_segment(&self) -> Option<Arc<RwLock<Segment>>> {
+        self.segments.read().unwrap().values().next_back().cloned()
+    }
+
+    // local_start_offset returns the base offset of the first segment on the local disk,
+    // the messages before it could only be read from the remote storage.
+    fn local_start_offset(&self) -> usize {
+        let segments = self.segments.read().unwrap();
+        segments
+            .keys()
+            .next()
+            .copied()
+            .unwrap_or(self.log_end_offset())
+    }
+
+    // remote_segment returns the segment fetched from the remote storage, which stores
+    // the given offset, if the offset is not on the local disk anymore.
+    fn remote_segment(&self, offset: usize) -> Result<Option<Arc<RwLock<Segment>>>, Error> {
+        match &self.tier {
+            Some(tier) => tier.segment(offset, self.local_start_offset()),
+            None => Ok(None),
+        }
+    }
+
+    // range_guard checks that a range read could start from the given offset,
+    // which is either stored in the log or is the end of it.
+    fn range_guard(&self, start: usize) -> Result<(), Error> {
+        let log_start_offset = self.log_start_offset();
+        let log_end_offset = self.log_end_offset();
+        if start < log_start_offset || start > log_end_offset {
+            return Err(StorageError::OffsetOutOfRange {
+                offset: start,
+                log_start_offset,
+                log_end_offset,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+
+    // segments_from returns the segments, which could store the messages starting
+    // from the given offset.
+    fn segments_from(
+        segments: &BTreeMap<usize, Arc<RwLock<Segment>>>,
+        start: usize,
+    ) -> impl Iterator<Item = &Arc<RwLock<Segment>>> {
+        let first = segments
+            .range(..=start)
+            .next_back()
+            .map_or(start, |(&base_offset, _)| base_offset);
+
+        segments.range(first..).map(|(_, s)| s)
+    }
+
+    /// Returns an iterator over the messages starting from the given offset.
+    ///
+    /// The offset could be the end of the log, then the iterator returns the messages
+    /// that will be written next.
+    pub fn iter_from(&self, offset: usize) -> Result<PartitionIter, Error> {
+        let log_start_offset = self.log_start_offset();
+        let log_end_offset = self.log_end_offset();
+        if offset < log_start_offset || offset > log_end_offset {
+            return Err(StorageError::OffsetOutOfRange {
+                offset,
+                log_start_offset,
+                log_end_offset,
+            }
+            .into());
+        }
+
+        Ok(PartitionIter::new(
+            self.segments.clone(),
+            self.end_offset.subscribe(),
+            self.truncations.clone(),
+            self.log_start_offset.clone(),
+            self.tier.clone(),
+            offset,
+        ))
+    }
+
+    /// Returns the offset of the first message with the timestamp at or after the given one.
+    ///
+    /// None means that every message in the partition is older than the timestamp, i.e. it is
+    /// beyond the end of the log and a consumer should wait for the messages that will be written next.
+    pub fn offset_for_timestamp(&self, timestamp: DateTime<Utc>) -> Result<Option<usize>, Error> {
+        let mut found = match &self.tier {
+            Some(tier) => tier.offset_for_timestamp(timestamp, self.local_start_offset())?,
+            None => None,
+        };
+
+        if found.is_none() {
+            let segments = self.segments.read().unwrap();
+            for s in segments.values() {
+                found = s.read().unwrap().offset_for_timestamp(timestamp)?;
+                if found.is_some() {
+                    break;
+                }
+            }
+        }
+
+        // the messages before the log start offset could still be in the segments.
+        Ok(found
+            .map(|offset| cmp::max(offset, self.log_start_offset()))
+            .filter(|&offset| offset < self.log_end_offset()))
+    }
+
+    /// Returns the offset of the first message that could be read from this log.
+    pub fn log_start_offset(&self) -> usize {
+        self.log_start_offset.load(Ordering::Acquire)
+    }
+
+    /// Returns the offset that will be given to the next written message.
+    pub fn log_end_offset(&self) -> usize {
+        self.next_offset.load(Ordering::Acquire)
+    }
+
+    /// Deletes the closed segments that are out of the retention limits from the config
+    /// and returns the amount of deleted segments.
+    ///
+    /// The segments in the remote storage count towards the limits as well and are deleted
+    /// from it, when they are out of them.
+    pub fn enforce_retention(&self) -> Result<usize, Error> {
+        Self::delete_expired_segments(
+            &self.path,
+            &self.segments,
+            &self.config,
+            &self.log_start_offset,
+            self.tier.as_deref(),
+        )
+    }
+
+    /// Copies the closed segments, which are not in the remote storage yet, to it and returns
+    /// the amount of copied segments. The local copies of the copied segments are then deleted
+    /// once they are out of the local retention age from the config.
+    ///
+    /// The retention task does it along with the retention, so it is needed only without it.
+    pub fn offload_segments(&self) -> Result<usize, Error> {
+        match &self.tier {
+            Some(tier) => {
+                Self::offload_closed_segments(&self.path, &self.segments, &self.config, tier)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Compacts the closed segments, so that only the last message for each key is left
+    /// in them, and returns the amount of removed messages.
+    pub fn compact(&self) -> Result<usize, Error> {
+        let _compaction = self.compaction.lock().unwrap();
+        Cleaner::new(&self.path, &self.config).compact(&self.segments)
+    }
+
+    // delete_expired_segments deletes the oldest closed segments while either the newest message
+    // in them is too old, the partition is too big or all of their messages are before the log
+    // start offset, and moves the log start offset after them.
+    // The closed segments are either on the local disk, in the remote tier or in both.
+    fn delete_expired_segments(
+        path: &str,
+        segments: &Segments,
+        config: &PartitionConfig,
+        log_start_offset: &AtomicUsize,
+        tier: Option<&RemoteTier>,
+    ) -> Result<usize, Error> {
+        let mut segments = segments.write().unwrap();
+
+        let now = Utc::now();
+        // the active segment is never deleted.
+        let Some((&active_offset, active)) = segments.iter().next_back() else {
+            return Ok(0);
+        };
+
+        let mut closed: BTreeMap<usize, (Option<DateTime<Utc>>, usize, usize)> = tier
+            .map(|tier| tier.manifests())
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|m| m.base_offset < active_offset)
+            .map(|m| {
+                (
+                    m.base_offset,
+                    (m.latest_timestamp, m.size_in_bytes, m.next_offset),
+                )
+            })
+            .collect();
+        for (&base_offset, segment) in segments.range(..active_offset) {
+            let segment = segment.read().unwrap();
+            closed.insert(
+                base_offset,
+                (
+                    segment.latest_timestamp(),
+                    segment.size_in_bytes(),
+                    segment.next_offset(),
+                ),
+            );
+        }
+
+        let mut total_bytes = active.read().unwrap().size_in_bytes()
+            + closed.values().map(|&(_, size, _)| size).sum::<usize>();
+
+        // the segments could have been active, when the records before the log start offset
+        // were deleted, so they are deleted here once they are closed.
+        let start_offset = log_start_offset.load(Ordering::Acquire);
+        let mut expired = Vec::new();
+        for (&base_offset, &(latest_timestamp, size, next_offset)) in closed.iter() {
+            let too_old = match (config.retention_age, latest_timestamp) {
+                (Some(max_age), Some(latest)) => {
+                    (now - latest).to_std().is_ok_and(|age| age >= max_age)
+                }
+                _ => false,
+            };
+            let too_big = config
+                .retention_bytes
+                .is_some_and(|max_bytes| total_bytes > max_bytes);
+
+            let before_start = next_offset <= start_offset;
+
+            if !too_old && !too_big && !before_start {
+                break;
+            }
+
+            total_bytes -= size;
+            expired.push(base_offset);
+        }
+
+        // the log start offset is moved after a segment before it is deleted, so that the reads,
+        // which find no segment for an offset, could tell that it is out of range.
+        // The remote objects are deleted after the segments are unlocked, since it could be slow.
+        let mut remote_keys = Vec::new();
+        let mut deleted_locally = false;
+        for base_offset in expired.iter() {
+            let (_, _, next_offset) = closed[base_offset];
+            log_start_offset.fetch_max(next_offset, Ordering::AcqRel);
+
+            if let Some(segment) = segments.remove(base_offset) {
+                segment.read().unwrap().delete()?;
+                deleted_locally = true;
+            }
+            if let Some(tier) = tier.filter(|tier| tier.contains(*base_offset)) {
+                remote_keys.extend(tier.remove(*base_offset)?);
+            }
+        }
+        if deleted_locally {
+            config.sync_dir(path)?;
+        }
+        drop(segments);
+
+        if let Some(tier) = tier {
+            tier.delete_objects(&remote_keys)?;
+        }
+        Ok(expired.len())
+    }
 
-        let segment = Segment::new(path.clone(), 0, Self::segment_size(0, segment_size))?;
+    // offload_closed_segments copies the closed segments to the remote tier and deletes
+    // the oldest local copies of the copied ones, while they are out of the local retention.
+    fn offload_closed_segments(
+        path: &str,
+        segments: &Segments,
+        config: &PartitionConfig,
+        tier: &RemoteTier,
+    ) -> Result<usize, Error> {
+        let pending: Vec<Arc<RwLock<Segment>>> = {
+            let segments = segments.read().unwrap();
+            let closed = segments.len().saturating_sub(1);
+            segments
+                .iter()
+                .take(closed)
+                .filter(|(&base_offset, _)| !tier.contains(base_offset))
+                .map(|(_, segment)| segment.clone())
+                .collect()
+        };
+
+        let mut offloaded = 0;
+        for segment in pending {
+            segment.write().unwrap().restore_indexes()?;
+            let manifest = tier.upload(path, &segment.read().unwrap())?;
+
+            // the segment could have been deleted or truncated during the upload,
+            // then the uploaded copy is stale.
+            let segments = segments.read().unwrap();
+            let base_offset = manifest.base_offset;
+            let closed = segments.keys().next_back() != Some(&base_offset)
+                && segments
+                    .get(&base_offset)
+                    .is_some_and(|s| Arc::ptr_eq(s, &segment));
+            match closed {
+                true => {
+                    tier.add(manifest)?;
+                    offloaded += 1;
+                }
+                false => tier.delete(base_offset)?,
+            }
+        }
+
+        let Some(max_age) = config.local_retention_age else {
+            return Ok(offloaded);
+        };
+
+        let now = Utc::now();
+        let mut segments = segments.write().unwrap();
+        let closed = segments.len().saturating_sub(1);
+
+        // only the oldest segments are deleted, so the local ones are left contiguous
+        // and the messages before the first of them are read from the remote tier.
+        let expired: Vec<usize> = segments
+            .iter()
+            .take(closed)
+            .take_while(|(&base_offset, segment)| {
+                tier.contains(base_offset)
+                    && segment
+                        .read()
+                        .unwrap()
+                        .latest_timestamp()
+                        .is_some_and(|latest| {
+                            (now - latest).to_std().is_ok_and(|age| age >= max_age)
+                        })
+            })
+            .map(|(&base_offset, _)| base_offset)
+            .collect();
+
+        for base_offset in expired.iter() {
+            if let Some(segment) = segments.remove(base_offset) {
+                segment.read().unwrap().delete()?;
+            }
+        }
+        if !expired.is_empty() {
+            config.sync_dir(path)?;
+        }
+
+        Ok(offloaded)
+    }
+
+    // sync_rolled_segment syncs the segment that will not be written to anymore,
+    // so that the syncs done later for the active segment cover every written message.
+    fn sync_rolled_segment(&self, segment: &Segment) -> Result<(), Error> {
+        match self.config.durability {
+            DurabilityPolicy::OsManaged => Ok(()),
+            _ => segment.sync(),
+        }
+    }
+
+    // start_sync_task spawns a task that syncs the active segment with the given interval
+    // until the partition is dropped.
+    fn start_sync_task(&self, interval: Duration) -> Result<(), Error> {
+        let segments = Arc::downgrade(&self.segments);
+        let synced_offset = self.synced_offset.clone();
+        let errors = self.background_error.clone();
+
+        Self::spawn_periodic("partition sync", interval, errors, move || {
+            Self::sync_active(&segments, &synced_offset)
+        })
+    }
+
+    // start_retention_task spawns a task that enforces the retention until the partition is dropped.
+    fn start_retention_task(&self, interval: Duration) -> Result<(), Error> {
+        let segments = Arc::downgrade(&self.segments);
+        let config = self.config.clone();
+        let log_start_offset = self.log_start_offset.clone();
+        let path = self.path.clone();
+        let tier = self.tier.clone();
+        let errors = self.background_error.clone();
+        let compaction = self.compaction.clone();
+
+        Self::spawn_periodic("partition retention", interval, errors, move || {
+            let Some(segments) = segments.upgrade() else {
+                return Ok(false);
+            };
+            Self::delete_expired_segments(
+                &path,
+                &segments,
+                &config,
+                &log_start_offset,
+                tier.as_deref(),
+            )?;
+            if config.cleanup_policy == CleanupPolicy::Compact {
+                let _compaction = compaction.lock().unwrap();
+                Cleaner::new(&path, &config).compact(&segments)?;
+            }
+            if let Some(tier) = &tier {
+                Self::offload_closed_segments(&path, &segments, &config, tier)?;
+            }
+            Ok(true)
+        })
+    }
+
+    // spawn_periodic spawns a task that runs the job on the blocking pool with the given interval
+    // until the job returns false, which it does after the partition is dropped.
+    // The failures of the job are stored into the errors, replacing the previous one.
+    fn spawn_periodic<F>(
+        name: &'static str,
+        interval: Duration,
+        errors: Arc<Mutex<Option<Error>>>,
+        job: F,
+    ) -> Result<(), Error>
+    where
+        F: Fn() -> Result<bool, Error> + Send + Sync + 'static,
+    {
+        let runtime = tokio::runtime::Handle::try_current()
+            .map_err(|e| Error::other(format!("{} requires a tokio runtime: {}", name, e)))?;
+
+        let job = Arc::new(job);
+        runtime.spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+
+                let job = job.clone();
+                let error = match tokio::task::spawn_blocking(move || job()).await {
+                    Ok(Ok(true)) => continue,
+                    Ok(Ok(false)) => return,
+                    Ok(Err(e)) => Error::new(e.kind(), format!("{} failed: {}", name, e)),
+                    Err(e) => Error::other(format!("{} panicked: {}", name, e)),
+                };
+                *errors.lock().unwrap() = Some(error);
+            }
+        });
+
+        Ok(())
+    }
+
+    // sync_active syncs the active segment of the partition and returns false
+    // if the partition does not exist anymore.
+    fn sync_active(segments: &Weak<Segments>, synced_offset: &AtomicUsize) -> Result<bool, Error> {
+        let Some(segments) = segments.upgrade() else {
+            return Ok(false);
+        };
+        let segments = segments.read().unwrap();
+
+        if let Some(segment) = segments.values().next_back() {
+            let segment = segment.read().unwrap();
+            segment.sync()?;
+            synced_offset.fetch_max(segment.next_offset(), Ordering::AcqRel);
+        }
+
+        Ok(true)
+    }
+
+    // init creates an empty partition, which continues the log in the remote tier if there is one.
+    fn init(
+        path: String,
+        number: usize,
+        config: PartitionConfig,
+        tier: Option<Arc<RemoteTier>>,
+    ) -> Result<Self, Error> {
+        let vfs = config.vfs.clone();
+        vfs.create_dir_all(&path)?;
+        // a checkpoint without the segments is left from a deleted log.
+        checkpoint::remove_log_start_offset(vfs.as_ref(), &path)?;
+
+        let next_offset = tier.as_ref().and_then(|t| t.end_offset()).unwrap_or(0);
+        let log_start_offset = tier.as_ref().and_then(|t| t.first_offset()).unwrap_or(0);
+        let segment = Segment::new(vfs, path.clone(), next_offset)?;
+        config.sync_dir(&path)?;
 
         Ok(Self {
             number,
             path,
-            segment_size,
-            next_offset: 0,
-            segments: Arc::new(RwLock::new(vec![Arc::new(Mutex::new(segment))])),
+            config,
+            next_offset: AtomicUsize::new(next_offset),
+            writer: Mutex::new(Writer::default()),
+            synced_offset: Arc::new(AtomicUsize::new(next_offset)),
+            log_start_offset: Arc::new(AtomicUsize::new(log_start_offset)),
+            end_offset: watch::Sender::new(next_offset),
+            truncations: Arc::new(Truncations::default()),
+            segments: Arc::new(RwLock::new(BTreeMap::from([(
+                next_offset,
+                Arc::new(RwLock::new(segment)),
+            )]))),
+            tier,
+            load_report: LoadReport::default(),
+            background_error: Arc::new(Mutex::new(None)),
+            compaction: Arc::new(Mutex::new(())),
         })
     }
 
-    fn load(path: String, number: usize, segment_size: usize) -> Result<Self, Error> {
-        let paths: Vec<String> = fs::read_dir(&path)?
-            .filter_map(|entry| entry.ok()) // Filter out errors
-            .filter(|entry| entry.path().is_file()) // Ensure it's a file
-            .filter(|entry| entry.path().extension() == Some("log".as_ref())) // Check for ".log" extension
-            .filter_map(|entry| entry.path().canonicalize().ok()) // Convert to absolute path
-            .filter_map(|path| path.to_str().map(|s| s.to_string())) // Convert to String
-            .collect();
+    fn load(
+        path: String,
+        number: usize,
+        config: PartitionConfig,
+        tier: Option<Arc<RemoteTier>>,
+    ) -> Result<Self, Error> {
+        let vfs = config.vfs.clone();
+        let mut load_report = LoadReport {
+            upgraded: upgrade::upgrade_partition(&config, &path)?,
+            ..Default::default()
+        };
+        let files = Self::list_segment_files(vfs.as_ref(), &path)?;
+
+        let mut base_offsets = Vec::with_capacity(files.len());
+        for (&base_offset, extensions) in files.iter() {
+            if !extensions.contains(LOG_EXTENSION) {
+                let extension = extensions.iter().next().map_or("", |e| e.as_str());
+                return Err(StorageError::OrphanIndexFile {
+                    path: format!("{}{}", path, Segment::file_name(base_offset, extension)),
+                }
+                .into());
+            }
+            base_offsets.push(base_offset);
+        }
+
+        let Some((&last, closed)) = base_offsets.split_last() else {
+            let mut partition = Self::init(path, number, config, tier)?;
+            partition.load_report = load_report;
+            return Ok(partition);
+        };
 
-        let mut segments = Vec::with_capacity(paths.len());
-        for i in 0..paths.len() {
-            let s = Segment::new(path.clone(), i as i32, Self::segment_size(i, segment_size))?;
-            segments.push(s);
+        let mut segments = BTreeMap::new();
+        let mut next_offset = base_offsets[0];
+
+        for &base_offset in closed.iter() {
+            Self::continuity_guard(&path, next_offset, base_offset)?;
+            // a closed segment without an index would be readable, but slow and without
+            // timestamps, so the indexes are rebuilt in that case, as well as the ones
+            // that point outside of the log.
+            let has_indexes = files[&base_offset].len() == SEGMENT_EXTENSIONS.len();
+            let s = match has_indexes {
+                true => match Segment::new(vfs.clone(), path.clone(), base_offset) {
+                    Err(e)
+                        if matches!(
+                            StorageError::of(&e),
+                            Some(StorageError::StaleIndexFile { .. })
+                        ) =>
+                    {
+                        Self::recover_segment(&config, &path, base_offset, &mut load_report)?
+                    }
+                    s => s?,
+                },
+                false => Self::recover_segment(&config, &path, base_offset, &mut load_report)?,
+            };
+            next_offset = s.next_offset();
+            segments.insert(base_offset, s);
         }
 
-        // next_offset is calculated by the size of the last segment + the sizes of all previous
-        // segments, which are always equal to segment_size
-        let next_offset = segment_size * (segments.len() - 1) + segments.last().unwrap().size()?;
+        // the last segment is the only one that was being written to, so it is the one that
+        // could be left inconsistent by an unclean shutdown.
+        Self::continuity_guard(&path, next_offset, last)?;
+        let s = Self::recover_segment(&config, &path, last, &mut load_report)?;
+        // whatever survived the restart is made durable before it is reported as synced.
+        s.sync()?;
+        // the repairs could have removed and rebuilt the indexes.
+        config.sync_dir(&path)?;
+        let next_offset = s.next_offset();
+        segments.insert(last, s);
+
+        // the oldest segments could be only in the remote tier, and the checkpoint could be
+        // ahead of the log, if its end was lost on an unclean shutdown.
+        let first_offset = tier
+            .as_ref()
+            .and_then(|t| t.first_offset())
+            .map_or(base_offsets[0], |offset| cmp::min(offset, base_offsets[0]));
+        let log_start_offset = checkpoint::read_log_start_offset(vfs.as_ref(), &path)?
+            .map_or(first_offset, |offset| cmp::max(offset, first_offset))
+            .min(next_offset);
 
         let segments = segments
             .into_iter()
-            .map(|s| Arc::new(Mutex::new(s)))
+            .map(|(base_offset, s)| (base_offset, Arc::new(RwLock::new(s))))
             .collect();
 
         Ok(Self {
             number,
             path,
-            segment_size,
-            next_offset,
+            config,
+            next_offset: AtomicUsize::new(next_offset),
+            writer: Mutex::new(Writer::default()),
+            synced_offset: Arc::new(AtomicUsize::new(next_offset)),
+            log_start_offset: Arc::new(AtomicUsize::new(log_start_offset)),
+            end_offset: watch::Sender::new(next_offset),
+            truncations: Arc::new(Truncations::default()),
             segments: Arc::new(RwLock::new(segments)),
+            tier,
+            load_report,
+            background_error: Arc::new(Mutex::new(None)),
+            compaction: Arc::new(Mutex::new(())),
         })
     }
 
-    fn segment_size(n: usize, ss: usize) -> (usize, usize) {
-        (n * ss, (n + 1) * ss)
+    // recover_segment opens the segment and repairs it if needed, adding what was repaired
+    // to the load report.
+    fn recover_segment(
+        config: &PartitionConfig,
+        path: &str,
+        base_offset: usize,
+        load_report: &mut LoadReport,
+    ) -> Result<Segment, Error> {
+        let (s, report) = Segment::recover(config.vfs.clone(), path.to_string(), base_offset)?;
+        if !report.is_clean() {
+            load_report.repaired.push(report);
+        }
+
+        Ok(s)
+    }
+
+    // continuity_guard checks that the segment with the given base offset starts right after
+    // the end of the previous one.
+    fn continuity_guard(path: &str, expected: usize, base_offset: usize) -> Result<(), Error> {
+        let partition = path.to_string();
+        match base_offset.cmp(&expected) {
+            cmp::Ordering::Equal => Ok(()),
+            cmp::Ordering::Greater => Err(StorageError::SegmentGap {
+                partition,
+                expected,
+                base_offset,
+            }
+            .into()),
+            cmp::Ordering::Less => Err(StorageError::SegmentOverlap {
+                partition,
+                expected,
+                base_offset,
+            }
+            .into()),
+        }
+    }
+
+    // list_segment_files returns the extensions of the segment files found in the directory
+    // by the base offsets of the segments. Files with the other extensions are ignored.
+    fn list_segment_files(
+        vfs: &dyn Vfs,
+        path: &str,
+    ) -> Result<BTreeMap<usize, HashSet<String>>, Error> {
+        let mut files: BTreeMap<usize, HashSet<String>> = BTreeMap::new();
+
+        for name in vfs.read_dir(path)? {
+            let entry_path = Path::new(path).join(name);
+
+            let Some(extension) = entry_path.extension().and_then(|e| e.to_str()) else {
+                continue;
+            };
+            if !SEGMENT_EXTENSIONS.contains(&extension) {
+                continue;
+            }
+
+            let base_offset = entry_path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(Segment::parse_base_offset)
+                .ok_or_else(|| StorageError::InvalidSegmentFile {
+                    path: entry_path.display().to_string(),
+                })?;
+
+            files
+                .entry(base_offset)
+                .or_default()
+                .insert(extension.to_string());
+        }
+
+        Ok(files)
     }
 }
 
@@ -147,8 +1345,8 @@ impl fmt::Display for Partition {
             "[Partition #{}, P: `{}`, SS: {:?}, NO: {}, SegLen: {}]",
             self.number,
             &self.path,
-            self.segment_size,
-            self.next_offset,
+            self.config.max_segment_messages,
+            self.log_end_offset(),
             self.segments.read().unwrap().len(),
         )
     }
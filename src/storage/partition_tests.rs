@@ -0,0 +1,712 @@
+use std::{
+    collections::BTreeMap,
+    io::{Error, ErrorKind},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use chrono::{TimeZone, Utc};
+
+use super::{
+    compression::Compression,
+    error::StorageError,
+    partition::{CleanupPolicy, Partition, PartitionConfig},
+    remote::RemoteStorage,
+    simulated_vfs::{Fault, SimulatedVfs},
+    vfs::{Vfs, VfsFile},
+};
+
+const PATH: &str = "data";
+
+fn config(vfs: &SimulatedVfs) -> PartitionConfig {
+    PartitionConfig {
+        retention_check_interval: None,
+        vfs: Arc::new(vfs.clone()),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn limits_that_fit_no_message_are_rejected() {
+    let vfs = SimulatedVfs::new();
+
+    for config in [
+        PartitionConfig {
+            max_segment_messages: 0,
+            ..config(&vfs)
+        },
+        PartitionConfig {
+            max_segment_bytes: 8,
+            ..config(&vfs)
+        },
+    ] {
+        let error = Partition::new(PATH.to_string(), 0, config).err().unwrap();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+    }
+    assert!(!vfs.exists(PATH));
+}
+
+#[test]
+fn compression_stats_count_only_the_records() {
+    for (compression, compressed) in [(Compression::None, false), (Compression::Zstd, true)] {
+        let vfs = SimulatedVfs::new();
+        let partition = Partition::new(
+            PATH.to_string(),
+            0,
+            PartitionConfig {
+                compression,
+                ..config(&vfs)
+            },
+        )
+        .unwrap();
+
+        partition
+            .write_batch(
+                (0..10)
+                    .map(|_| (Utc::now(), None, vec![7; 100], vec![]))
+                    .collect(),
+            )
+            .unwrap();
+
+        let stats = partition.compression_stats();
+        assert!(stats.raw_bytes > 1000);
+        match compressed {
+            true => assert!(stats.stored_bytes < stats.raw_bytes),
+            false => assert_eq!(stats.stored_bytes, stats.raw_bytes),
+        }
+    }
+}
+
+#[test]
+fn range_read_stops_at_the_first_batch_over_the_limit() {
+    // the second segment starts with a small batch, which fits into what is left of the limit
+    // after the big one, but it could not be returned without the message before it.
+    let vfs = SimulatedVfs::new();
+    let partition = Partition::new(
+        PATH.to_string(),
+        0,
+        PartitionConfig {
+            max_segment_messages: 2,
+            ..config(&vfs)
+        },
+    )
+    .unwrap();
+    for value in [vec![1; 10], vec![2; 1000], vec![3; 10], vec![4; 10]] {
+        partition.write(Utc::now(), None, value, vec![]).unwrap();
+    }
+
+    let messages = partition.read_range(0, 10, 500).unwrap();
+    let offsets: Vec<usize> = messages.iter().map(|m| m.offset).collect();
+    assert_eq!(offsets, vec![0]);
+
+    let regions = partition.read_range_raw(0, 10, 500).unwrap();
+    assert_eq!(regions.iter().map(|r| r.records).sum::<usize>(), 1);
+
+    let offsets: Vec<usize> = partition
+        .read_range(1, 10, 500)
+        .unwrap()
+        .iter()
+        .map(|m| m.offset)
+        .collect();
+    assert_eq!(offsets, vec![1]);
+}
+
+#[test]
+fn rejected_batch_writes_nothing() {
+    let vfs = SimulatedVfs::new();
+    let partition = Partition::new(
+        PATH.to_string(),
+        0,
+        PartitionConfig {
+            max_segment_messages: 2,
+            ..config(&vfs)
+        },
+    )
+    .unwrap();
+    partition.write(Utc::now(), None, vec![1], vec![]).unwrap();
+
+    // the last message is out of the nanosecond range, or too far from the first one.
+    let past = Utc.with_ymd_and_hms(1700, 1, 1, 0, 0, 0).unwrap();
+    let future = Utc.with_ymd_and_hms(2200, 1, 1, 0, 0, 0).unwrap();
+    let far_future = Utc.with_ymd_and_hms(2300, 1, 1, 0, 0, 0).unwrap();
+    for (first, last) in [(Utc::now(), far_future), (past, future)] {
+        let batch = [first, first, first, last]
+            .into_iter()
+            .map(|timestamp| (timestamp, None, vec![2], vec![]))
+            .collect();
+        let error = partition.write_batch(batch).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(partition.log_end_offset(), 1);
+    }
+
+    partition.write(Utc::now(), None, vec![3], vec![]).unwrap();
+    assert_eq!(partition.log_end_offset(), 2);
+    assert_eq!(partition.read(1).unwrap().value, vec![3]);
+}
+
+fn compacted_config(vfs: &SimulatedVfs, tombstone_retention: Duration) -> PartitionConfig {
+    PartitionConfig {
+        max_segment_messages: 4,
+        cleanup_policy: CleanupPolicy::Compact,
+        tombstone_retention,
+        ..config(vfs)
+    }
+}
+
+// write_keyed writes the messages with the given keys and values, an empty value is a tombstone.
+fn write_keyed(partition: &Partition, messages: &[(&str, &str)]) {
+    for (key, value) in messages {
+        partition
+            .write(
+                Utc::now(),
+                Some(key.as_bytes().to_vec()),
+                value.as_bytes().to_vec(),
+                vec![],
+            )
+            .unwrap();
+    }
+}
+
+// read_all returns the (offset, value) pairs of all of the messages in the partition.
+fn read_all(partition: &Partition) -> Vec<(usize, String)> {
+    partition
+        .read_range(partition.log_start_offset(), usize::MAX, usize::MAX)
+        .unwrap()
+        .into_iter()
+        .map(|m| (m.offset, String::from_utf8(m.value).unwrap()))
+        .collect()
+}
+
+#[test]
+fn compaction_keeps_the_latest_value_per_key() {
+    let vfs = SimulatedVfs::new();
+    let partition = Partition::new(
+        PATH.to_string(),
+        0,
+        compacted_config(&vfs, Duration::from_secs(3600)),
+    )
+    .unwrap();
+    write_keyed(
+        &partition,
+        &[
+            ("a", "a0"),
+            ("b", "b0"),
+            ("a", "a1"),
+            ("c", "c0"),
+            ("b", "b1"),
+            ("a", "a2"),
+            ("c", "c1"),
+            ("d", "d0"),
+            ("b", "b2"),
+        ],
+    );
+
+    assert_eq!(partition.compact().unwrap(), 4);
+    // the last message of every closed segment is left, even if there is a newer one.
+    let expected = vec![
+        (3, "c0".to_string()),
+        (5, "a2".to_string()),
+        (6, "c1".to_string()),
+        (7, "d0".to_string()),
+        (8, "b2".to_string()),
+    ];
+    assert_eq!(read_all(&partition), expected);
+
+    // the offsets keep their gaps after a reload, and the log continues after them.
+    drop(partition);
+    let partition = Partition::new(
+        PATH.to_string(),
+        0,
+        compacted_config(&vfs, Duration::from_secs(3600)),
+    )
+    .unwrap();
+    assert_eq!(read_all(&partition), expected);
+    assert!(partition.read(4).is_err());
+    assert_eq!(partition.log_end_offset(), 9);
+    write_keyed(&partition, &[("a", "a3")]);
+    assert_eq!(partition.read(9).unwrap().value, b"a3".to_vec());
+}
+
+#[test]
+fn compaction_removes_tombstones_after_their_retention() {
+    for (tombstone_retention, kept) in [
+        (
+            Duration::from_secs(3600),
+            vec![(1, ""), (2, ""), (3, "c0"), (4, "d0")],
+        ),
+        (Duration::ZERO, vec![(3, "c0"), (4, "d0")]),
+    ] {
+        let vfs = SimulatedVfs::new();
+        let partition = Partition::new(
+            PATH.to_string(),
+            0,
+            compacted_config(&vfs, tombstone_retention),
+        )
+        .unwrap();
+        write_keyed(
+            &partition,
+            &[("a", "a0"), ("a", ""), ("b", ""), ("c", "c0"), ("d", "d0")],
+        );
+
+        partition.compact().unwrap();
+        let kept: Vec<(usize, String)> = kept
+            .into_iter()
+            .map(|(offset, value)| (offset, value.to_string()))
+            .collect();
+        assert_eq!(read_all(&partition), kept);
+    }
+}
+
+#[test]
+fn concurrent_compactions_do_not_interfere() {
+    let vfs = SimulatedVfs::new();
+    let partition = Partition::new(
+        PATH.to_string(),
+        0,
+        compacted_config(&vfs, Duration::from_secs(3600)),
+    )
+    .unwrap();
+    let messages: Vec<(String, String)> = (0..200)
+        .map(|i| (format!("key {}", i % 10), format!("value {}", i)))
+        .collect();
+    let messages: Vec<(&str, &str)> = messages
+        .iter()
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    write_keyed(&partition, &messages);
+
+    thread::scope(|scope| {
+        for _ in 0..4 {
+            scope.spawn(|| partition.compact().unwrap());
+        }
+    });
+
+    let values = read_all(&partition);
+    for i in 190..200 {
+        assert!(values.contains(&(i, format!("value {}", i))));
+    }
+}
+
+// MemoryStorage is a remote storage, which keeps the objects in the memory.
+#[derive(Debug, Default)]
+struct MemoryStorage {
+    objects: Mutex<BTreeMap<String, Vec<u8>>>,
+}
+
+impl RemoteStorage for MemoryStorage {
+    fn upload(&self, source: &dyn VfsFile, key: &str) -> Result<(), Error> {
+        let mut data = vec![0u8; source.size()? as usize];
+        source.read_exact_at(&mut data, 0)?;
+        self.objects.lock().unwrap().insert(key.to_string(), data);
+        Ok(())
+    }
+
+    fn download(&self, key: &str, destination: &dyn VfsFile) -> Result<(), Error> {
+        let objects = self.objects.lock().unwrap();
+        let data = objects
+            .get(key)
+            .ok_or_else(|| Error::new(ErrorKind::NotFound, key.to_string()))?;
+        destination.set_len(0)?;
+        destination.write_all_at(data, 0)
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let objects = self.objects.lock().unwrap();
+        Ok(objects
+            .keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect())
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        self.objects.lock().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+// tiered_config copies every closed segment of two messages to the storage and deletes
+// its local copy right away, keeping only one fetched segment in the cache.
+fn tiered_config(vfs: &SimulatedVfs, storage: &Arc<MemoryStorage>) -> PartitionConfig {
+    PartitionConfig {
+        max_segment_messages: 2,
+        remote_storage: Some(storage.clone()),
+        local_retention_age: Some(Duration::ZERO),
+        remote_cache_segments: 1,
+        ..config(vfs)
+    }
+}
+
+// logs returns the names of the logs in the given directory.
+fn logs(vfs: &SimulatedVfs, path: &str) -> Vec<String> {
+    let mut logs: Vec<String> = vfs
+        .read_dir(path)
+        .unwrap()
+        .into_iter()
+        .filter(|name| name.ends_with(".log"))
+        .collect();
+    logs.sort();
+    logs
+}
+
+fn write_values(partition: &Partition, count: usize) {
+    for i in 0..count {
+        partition
+            .write(
+                Utc::now(),
+                None,
+                format!("value {}", i).into_bytes(),
+                vec![],
+            )
+            .unwrap();
+    }
+}
+
+#[test]
+fn offloaded_segments_are_read_through_the_cache() {
+    let vfs = SimulatedVfs::new();
+    let storage = Arc::new(MemoryStorage::default());
+    let partition = Partition::new(PATH.to_string(), 0, tiered_config(&vfs, &storage)).unwrap();
+    write_values(&partition, 7);
+
+    assert_eq!(partition.offload_segments().unwrap(), 3);
+    assert_eq!(
+        logs(&vfs, "data/00000000"),
+        vec!["00000000000000000006.log"]
+    );
+    assert_eq!(storage.list("00000000/").unwrap().len(), 3 * 4);
+
+    assert_read_through_the_cache(&vfs, &partition);
+    drop(partition);
+
+    let partition = Partition::new(PATH.to_string(), 0, tiered_config(&vfs, &storage)).unwrap();
+    assert_read_through_the_cache(&vfs, &partition);
+}
+
+// assert_read_through_the_cache checks that the messages of the partition written
+// by write_values are read, while only one of the offloaded segments is cached.
+fn assert_read_through_the_cache(vfs: &SimulatedVfs, partition: &Partition) {
+    assert_eq!(partition.log_start_offset(), 0);
+    for offset in 0..7 {
+        let message = partition.read(offset).unwrap();
+        assert_eq!(message.value, format!("value {}", offset).into_bytes());
+    }
+    assert_eq!(logs(vfs, "data/00000000/remote-cache").len(), 1);
+
+    let offsets: Vec<usize> = partition
+        .read_range(1, 10, 1 << 20)
+        .unwrap()
+        .iter()
+        .map(|m| m.offset)
+        .collect();
+    assert_eq!(offsets, (1..7).collect::<Vec<usize>>());
+}
+
+#[test]
+fn removed_indexes_are_restored_before_the_upload() {
+    let vfs = SimulatedVfs::new();
+    let storage = Arc::new(MemoryStorage::default());
+    let partition = Partition::new(PATH.to_string(), 0, tiered_config(&vfs, &storage)).unwrap();
+    let timestamp = Utc::now() - chrono::Duration::minutes(1);
+
+    // the write of the second message fails to extend the time index, so its file is removed.
+    vfs.fail_files(".timeindex", Some(Fault::Io));
+    for (seconds, value) in [(0, "value 0"), (10, "value 1")] {
+        partition
+            .write(
+                timestamp + chrono::Duration::seconds(seconds),
+                None,
+                value.as_bytes().to_vec(),
+                vec![],
+            )
+            .unwrap();
+    }
+    vfs.fail_files(".timeindex", None);
+    write_values(&partition, 1);
+
+    assert_eq!(partition.offload_segments().unwrap(), 1);
+    drop(partition);
+
+    let partition = Partition::new(PATH.to_string(), 0, tiered_config(&vfs, &storage)).unwrap();
+    assert_eq!(
+        logs(&vfs, "data/00000000"),
+        vec!["00000000000000000002.log"]
+    );
+    let offset = partition
+        .offset_for_timestamp(timestamp + chrono::Duration::seconds(5))
+        .unwrap();
+    assert_eq!(offset, Some(1));
+}
+
+#[test]
+fn expired_segments_are_deleted_from_the_remote_storage() {
+    let vfs = SimulatedVfs::new();
+    let storage = Arc::new(MemoryStorage::default());
+    let partition = Partition::new(
+        PATH.to_string(),
+        0,
+        PartitionConfig {
+            retention_bytes: Some(0),
+            ..tiered_config(&vfs, &storage)
+        },
+    )
+    .unwrap();
+    write_values(&partition, 5);
+    assert_eq!(partition.offload_segments().unwrap(), 2);
+
+    assert_eq!(partition.enforce_retention().unwrap(), 2);
+    assert!(storage.list("").unwrap().is_empty());
+    assert_eq!(partition.log_start_offset(), 4);
+}
+
+#[test]
+fn truncation_to_an_offloaded_offset_is_not_supported() {
+    let vfs = SimulatedVfs::new();
+    let storage = Arc::new(MemoryStorage::default());
+    let partition = Partition::new(PATH.to_string(), 0, tiered_config(&vfs, &storage)).unwrap();
+    write_values(&partition, 7);
+    partition.offload_segments().unwrap();
+
+    let error = partition.truncate_to(3).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Unsupported);
+    assert_eq!(partition.log_end_offset(), 7);
+    assert_eq!(partition.read(3).unwrap().offset, 3);
+
+    partition.truncate_to(6).unwrap();
+    assert_eq!(partition.log_end_offset(), 6);
+}
+
+#[test]
+fn iterator_continues_from_the_truncation_offset() {
+    let vfs = SimulatedVfs::new();
+    let partition = Partition::new(PATH.to_string(), 0, config(&vfs)).unwrap();
+    write_values(&partition, 5);
+
+    let mut iter = partition.iter_from(0).unwrap();
+    for offset in 0..4 {
+        assert_eq!(iter.next().unwrap().unwrap().offset, offset);
+    }
+
+    // the messages written after the truncation are returned, even though the end of the log
+    // is past the iterator again by the time it is called.
+    partition.truncate_to(3).unwrap();
+    partition.truncate_to(2).unwrap();
+    write_values(&partition, 3);
+
+    let read: Vec<(usize, Vec<u8>)> = iter
+        .by_ref()
+        .map(|m| m.unwrap())
+        .map(|m| (m.offset, m.value))
+        .collect();
+    let expected: Vec<(usize, Vec<u8>)> = (0..3)
+        .map(|i| (2 + i, format!("value {}", i).into_bytes()))
+        .collect();
+    assert_eq!(read, expected);
+    assert_eq!(iter.next_offset(), 5);
+}
+
+#[tokio::test]
+async fn iterator_waits_for_the_written_messages() {
+    let vfs = SimulatedVfs::new();
+    let partition = Partition::new(PATH.to_string(), 0, config(&vfs)).unwrap();
+    partition
+        .write_batch(
+            (0..3)
+                .map(|_| (Utc::now(), None, vec![7], vec![]))
+                .collect(),
+        )
+        .unwrap();
+
+    let mut iter = partition.iter_from(0).unwrap();
+    for offset in 0..3 {
+        assert_eq!(iter.next_or_wait().await.unwrap().offset, offset);
+    }
+
+    let next = tokio::spawn(async move { iter.next_or_wait().await.map(|m| m.offset) });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!next.is_finished());
+
+    partition.write(Utc::now(), None, vec![7], vec![]).unwrap();
+    assert_eq!(next.await.unwrap().unwrap(), 3);
+}
+
+#[tokio::test]
+async fn waiting_iterator_continues_from_the_truncation_offset() {
+    let vfs = SimulatedVfs::new();
+    let partition = Partition::new(PATH.to_string(), 0, config(&vfs)).unwrap();
+    write_values(&partition, 3);
+
+    let mut iter = partition.iter_from(3).unwrap();
+    let next = tokio::spawn(async move { iter.next_or_wait().await.map(|m| m.offset) });
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    // the truncation wakes the iterator up, but it waits again for the offset it moved to.
+    partition.truncate_to(1).unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert!(!next.is_finished());
+
+    partition.write(Utc::now(), None, vec![7], vec![]).unwrap();
+    assert_eq!(next.await.unwrap().unwrap(), 1);
+}
+
+#[tokio::test]
+async fn waiting_iterator_continues_from_the_log_start_offset() {
+    let vfs = SimulatedVfs::new();
+    let partition = Partition::new(PATH.to_string(), 0, config(&vfs)).unwrap();
+    partition
+        .write_batch(
+            (0..3)
+                .map(|_| (Utc::now(), None, vec![7], vec![]))
+                .collect(),
+        )
+        .unwrap();
+
+    // the message before the new log start offset is skipped, though it is already buffered.
+    let mut iter = partition.iter_from(0).unwrap();
+    assert_eq!(iter.next_or_wait().await.unwrap().offset, 0);
+
+    partition.delete_records_before(2).unwrap();
+    assert_eq!(iter.next_or_wait().await.unwrap().offset, 2);
+}
+
+#[test]
+fn retention_deletes_segments_closed_before_the_log_start_offset() {
+    let vfs = SimulatedVfs::new();
+    let partition = Partition::new(
+        PATH.to_string(),
+        0,
+        PartitionConfig {
+            max_segment_messages: 1,
+            ..config(&vfs)
+        },
+    )
+    .unwrap();
+    write_values(&partition, 1);
+
+    // the only segment is active, so it is left on the disk.
+    assert_eq!(partition.delete_records_before(1).unwrap(), 0);
+    write_values(&partition, 1);
+    assert_eq!(logs(&vfs, "data/00000000").len(), 2);
+
+    assert_eq!(partition.enforce_retention().unwrap(), 1);
+    assert_eq!(
+        logs(&vfs, "data/00000000"),
+        vec!["00000000000000000001.log"]
+    );
+    assert_eq!(partition.log_start_offset(), 1);
+    assert_eq!(partition.read(1).unwrap().value, b"value 0".to_vec());
+}
+
+fn assert_out_of_range_error(error: &Error) {
+    assert!(
+        matches!(
+            StorageError::of(error),
+            Some(StorageError::OffsetOutOfRange { .. })
+        ),
+        "unexpected error: {}",
+        error
+    );
+}
+
+fn assert_out_of_range<T>(result: Result<T, Error>) {
+    match result {
+        Ok(_) => panic!("the offset is read"),
+        Err(error) => assert_out_of_range_error(&error),
+    }
+}
+
+#[test]
+fn retention_deletes_segments_out_of_the_age() {
+    let vfs = SimulatedVfs::new();
+    let partition = Partition::new(
+        PATH.to_string(),
+        0,
+        PartitionConfig {
+            max_segment_messages: 2,
+            retention_age: Some(Duration::from_secs(3600)),
+            ..config(&vfs)
+        },
+    )
+    .unwrap();
+    let old = Utc::now() - chrono::Duration::hours(2);
+    for timestamp in [old, old, Utc::now(), Utc::now(), old] {
+        partition.write(timestamp, None, vec![7], vec![]).unwrap();
+    }
+
+    // the newest message of the second segment is recent, so it stops the retention,
+    // and the old message in the active segment is kept as well.
+    assert_eq!(partition.enforce_retention().unwrap(), 1);
+    assert_eq!(logs(&vfs, "data/00000000").len(), 2);
+    assert_eq!(partition.log_start_offset(), 2);
+    assert_out_of_range(partition.read(1));
+    assert_out_of_range(partition.read_range(0, 10, 1 << 20));
+    assert_eq!(partition.read(4).unwrap().value, vec![7]);
+    assert_eq!(partition.enforce_retention().unwrap(), 0);
+}
+
+#[test]
+fn retention_deletes_the_oldest_segments_over_the_size() {
+    let vfs = SimulatedVfs::new();
+    let partition = Partition::new(
+        PATH.to_string(),
+        0,
+        PartitionConfig {
+            max_segment_messages: 2,
+            retention_bytes: Some(1000),
+            ..config(&vfs)
+        },
+    )
+    .unwrap();
+    for size in [1000, 10, 10, 10, 10] {
+        partition
+            .write(Utc::now(), None, vec![7; size], vec![])
+            .unwrap();
+    }
+
+    // the partition fits into the limit once the first segment is deleted.
+    assert_eq!(partition.enforce_retention().unwrap(), 1);
+    assert_eq!(logs(&vfs, "data/00000000").len(), 2);
+    assert_eq!(partition.log_start_offset(), 2);
+    assert_out_of_range(partition.read(0));
+    assert_eq!(partition.read(2).unwrap().value, vec![7; 10]);
+    assert_eq!(partition.enforce_retention().unwrap(), 0);
+}
+
+#[test]
+fn reads_of_segments_deleted_by_the_retention_are_out_of_range() {
+    for _ in 0..20 {
+        let vfs = SimulatedVfs::new();
+        let partition = Arc::new(
+            Partition::new(
+                PATH.to_string(),
+                0,
+                PartitionConfig {
+                    max_segment_messages: 1,
+                    retention_bytes: Some(1),
+                    ..config(&vfs)
+                },
+            )
+            .unwrap(),
+        );
+        write_values(&partition, 100);
+
+        let reader = {
+            let partition = partition.clone();
+            thread::spawn(move || {
+                while partition.log_start_offset() < 99 {
+                    for offset in 0..99 {
+                        if let Err(error) = partition.read(offset) {
+                            assert_out_of_range_error(&error);
+                        }
+                        if let Err(error) = partition.read_range(offset, 10, 1 << 20) {
+                            assert_out_of_range_error(&error);
+                        }
+                    }
+                }
+            })
+        };
+        assert_eq!(partition.enforce_retention().unwrap(), 99);
+        reader.join().unwrap();
+    }
+}
@@ -0,0 +1,700 @@
+use std::{io::ErrorKind, sync::Arc};
+
+use chrono::Utc;
+
+use super::{
+    async_partition::{AsyncPartition, Batch, Durability},
+    error::StorageError,
+    format::{self, BATCH_HEADER_SIZE, FILE_HEADER_SIZE},
+    partition::{DurabilityPolicy, Partition, PartitionConfig},
+    simulated_vfs::{Fault, SimulatedVfs},
+    vfs::Vfs,
+};
+
+const PATH: &str = "data";
+
+/// SEGMENT_MESSAGES is small, so that the tests roll a few segments.
+const SEGMENT_MESSAGES: usize = 4;
+
+fn config(vfs: &SimulatedVfs, durability: DurabilityPolicy) -> PartitionConfig {
+    PartitionConfig {
+        max_segment_messages: SEGMENT_MESSAGES,
+        durability,
+        retention_check_interval: None,
+        vfs: Arc::new(vfs.clone()),
+        ..Default::default()
+    }
+}
+
+fn value(offset: usize) -> Vec<u8> {
+    format!("message {}", offset).into_bytes()
+}
+
+// write writes the messages one by one until one of them fails
+// and returns the amount of acknowledged ones.
+fn write(partition: &Partition, count: usize) -> usize {
+    let first = partition.log_end_offset();
+    for offset in first..first + count {
+        if partition
+            .write(Utc::now(), None, value(offset), vec![])
+            .is_err()
+        {
+            return offset - first;
+        }
+    }
+
+    count
+}
+
+// assert_prefix loads the partition and checks that it holds the messages from 0 to somewhere
+// between the acknowledged and the attempted ones, and that it could be written to.
+fn assert_prefix(vfs: &SimulatedVfs, acknowledged: usize, attempted: usize) -> Partition {
+    let partition = Partition::new(PATH.to_string(), 0, config(vfs, DurabilityPolicy::Always))
+        .expect("the partition is loaded");
+
+    let end = partition.log_end_offset();
+    assert!(
+        (acknowledged..=attempted).contains(&end),
+        "the log ends at {}, but {} messages were acknowledged and {} attempted",
+        end,
+        acknowledged,
+        attempted
+    );
+    for offset in 0..end {
+        let message = partition.read(offset).expect("the message is read");
+        assert_eq!(message.offset, offset);
+        assert_eq!(message.value, value(offset));
+    }
+
+    assert_eq!(write(&partition, 1), 1);
+    assert_eq!(partition.read(end).unwrap().value, value(end));
+    partition
+}
+
+#[test]
+fn unsynced_writes_are_dropped_on_crash() {
+    let vfs = SimulatedVfs::new();
+    let partition = Partition::new(
+        PATH.to_string(),
+        0,
+        config(&vfs, DurabilityPolicy::EveryMessages(3)),
+    )
+    .unwrap();
+
+    assert_eq!(write(&partition, 10), 10);
+    let synced = partition
+        .last_synced_offset()
+        .map_or(0, |offset| offset + 1);
+    assert!(synced < 10);
+
+    vfs.crash();
+    drop(partition);
+
+    let partition = assert_prefix(&vfs, synced, 10);
+    assert_eq!(partition.log_end_offset(), synced + 1);
+}
+
+// async_partition opens a partition, which syncs only every 100 messages,
+// behind an async handle.
+fn async_partition(vfs: &SimulatedVfs) -> AsyncPartition {
+    let partition = Partition::new(
+        PATH.to_string(),
+        0,
+        config(vfs, DurabilityPolicy::EveryMessages(100)),
+    )
+    .unwrap();
+    AsyncPartition::new(partition).unwrap()
+}
+
+fn batch(offsets: std::ops::Range<usize>) -> Batch {
+    offsets
+        .map(|offset| (Utc::now(), None, value(offset), vec![]))
+        .collect()
+}
+
+#[tokio::test]
+async fn written_appends_are_readable_but_not_synced() {
+    let vfs = SimulatedVfs::new();
+    let partition = async_partition(&vfs);
+
+    let range = partition
+        .append(batch(0..3), Durability::Written)
+        .await
+        .unwrap();
+    assert_eq!(range, 0..3);
+    for offset in range {
+        assert_eq!(partition.read(offset).await.unwrap().value, value(offset));
+    }
+
+    vfs.crash();
+    drop(partition);
+
+    let partition = assert_prefix(&vfs, 0, 0);
+    assert_eq!(partition.log_end_offset(), 1);
+}
+
+#[tokio::test]
+async fn synced_appends_survive_a_crash() {
+    let vfs = SimulatedVfs::new();
+    let partition = async_partition(&vfs);
+
+    let written = partition.append(batch(0..2), Durability::Written);
+    let synced = partition.append(batch(2..5), Durability::Synced);
+    let (written, synced) = tokio::join!(written, synced);
+    assert_eq!(written.unwrap(), 0..2);
+    assert_eq!(synced.unwrap(), 2..5);
+
+    // the sync of the later append covers the earlier written ones as well.
+    vfs.crash();
+    drop(partition);
+
+    assert_prefix(&vfs, 5, 5);
+}
+
+#[test]
+fn torn_writes_leave_a_prefix_of_acknowledged_writes() {
+    const MESSAGES: usize = 3 * SEGMENT_MESSAGES + 1;
+
+    for tear_at in 0.. {
+        let vfs = SimulatedVfs::new();
+        vfs.tear_write_at(tear_at);
+
+        // the tear could also hit the headers of the files written by the creation.
+        let acknowledged =
+            match Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)) {
+                Ok(partition) => write(&partition, MESSAGES),
+                Err(_) => 0,
+            };
+        if !vfs.is_halted() {
+            assert_eq!(acknowledged, MESSAGES);
+            break;
+        }
+
+        vfs.crash();
+        assert_prefix(&vfs, acknowledged, acknowledged + 1);
+    }
+}
+
+#[test]
+fn no_space_fails_the_write() {
+    let vfs = SimulatedVfs::new();
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+    assert_eq!(write(&partition, 6), 6);
+
+    vfs.fail(Some(Fault::NoSpace));
+    let error = partition
+        .write(Utc::now(), None, value(6), vec![])
+        .unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(28));
+    assert!(partition.read(6).is_err());
+
+    vfs.fail(None);
+    assert_eq!(write(&partition, 3), 3);
+    drop(partition);
+
+    assert_prefix(&vfs, 9, 9);
+}
+
+#[test]
+fn io_error_is_reported_and_the_log_is_recovered() {
+    let vfs = SimulatedVfs::new();
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+    assert_eq!(write(&partition, 5), 5);
+
+    vfs.fail(Some(Fault::Io));
+    let error = partition
+        .write(Utc::now(), None, value(5), vec![])
+        .unwrap_err();
+    assert_eq!(error.raw_os_error(), Some(5));
+
+    vfs.crash();
+    drop(partition);
+
+    assert_prefix(&vfs, 5, 5);
+}
+
+#[test]
+fn interrupted_recovery_is_repeated() {
+    const MESSAGES: usize = 2 * SEGMENT_MESSAGES + 2;
+
+    // the last write is torn, so that the recovery has to truncate the log
+    // and rebuild the indexes.
+    let vfs = SimulatedVfs::new();
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+    assert_eq!(write(&partition, MESSAGES), MESSAGES);
+    vfs.tear_write_at(20);
+    assert_eq!(write(&partition, 1), 0);
+    vfs.crash();
+    drop(partition);
+
+    for tear_at in 0.. {
+        vfs.tear_write_at(tear_at);
+        let loaded = Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always));
+        if !vfs.is_halted() {
+            assert_eq!(loaded.unwrap().log_end_offset(), MESSAGES);
+            break;
+        }
+
+        assert!(loaded.is_err());
+        vfs.crash();
+    }
+
+    vfs.fail(Some(Fault::Io));
+    let error = Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always))
+        .err()
+        .expect("the load fails");
+    assert_eq!(error.raw_os_error(), Some(5));
+    vfs.crash();
+
+    assert_prefix(&vfs, MESSAGES, MESSAGES);
+}
+
+// segment_file returns the path of the file of the segment with the given base offset.
+fn segment_file(base_offset: usize, extension: &str) -> String {
+    format!("{}/00000000/{:020}.{}", PATH, base_offset, extension)
+}
+
+// write_segments writes three full segments and closes the partition.
+fn write_segments(vfs: &SimulatedVfs) {
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(vfs, DurabilityPolicy::Always)).unwrap();
+    assert_eq!(
+        write(&partition, 3 * SEGMENT_MESSAGES),
+        3 * SEGMENT_MESSAGES
+    );
+}
+
+#[test]
+fn stale_index_of_a_closed_segment_is_rebuilt() {
+    let vfs = SimulatedVfs::new();
+    write_segments(&vfs);
+
+    let index = vfs.open(&segment_file(0, "index")).unwrap();
+    let size = index.size().unwrap();
+    index
+        .write_all_at(&format::encode_offset_entry(2, 1 << 20), size)
+        .unwrap();
+
+    let partition = assert_prefix(&vfs, 3 * SEGMENT_MESSAGES, 3 * SEGMENT_MESSAGES);
+    assert!(partition
+        .load_report()
+        .repaired
+        .iter()
+        .any(|r| r.segment.starts_with("[Segment 0 ") && r.offset_index_rebuilt));
+    drop(partition);
+
+    // the rebuilt index is shorter, so the stale entry is cut off the file.
+    let index = vfs.open(&segment_file(0, "index")).unwrap();
+    assert_eq!(index.size().unwrap(), size);
+}
+
+#[test]
+fn invalid_segment_files_fail_the_load() {
+    let rename_segment = |vfs: &SimulatedVfs, from: usize, to: usize| {
+        for extension in ["log", "index", "timeindex"] {
+            vfs.rename(&segment_file(from, extension), &segment_file(to, extension))
+                .unwrap();
+        }
+    };
+
+    for case in ["gap", "overlap", "orphan", "bad name"] {
+        let vfs = SimulatedVfs::new();
+        write_segments(&vfs);
+        match case {
+            "gap" => rename_segment(&vfs, 4, 5),
+            "overlap" => rename_segment(&vfs, 8, 7),
+            "orphan" => vfs.remove_file(&segment_file(4, "log")).unwrap(),
+            _ => {
+                vfs.open(&format!("{}/00000000/segment.log", PATH)).unwrap();
+            }
+        }
+
+        let error = Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always))
+            .err()
+            .unwrap_or_else(|| panic!("{}: the load succeeds", case));
+        let matched = match (case, StorageError::of(&error)) {
+            (
+                "gap",
+                Some(StorageError::SegmentGap {
+                    expected,
+                    base_offset,
+                    ..
+                }),
+            ) => (*expected, *base_offset) == (4, 5),
+            (
+                "overlap",
+                Some(StorageError::SegmentOverlap {
+                    expected,
+                    base_offset,
+                    ..
+                }),
+            ) => (*expected, *base_offset) == (8, 7),
+            ("orphan", Some(StorageError::OrphanIndexFile { path })) => {
+                path.contains(&format!("{:020}", 4))
+            }
+            ("bad name", Some(StorageError::InvalidSegmentFile { path })) => {
+                path.ends_with("segment.log")
+            }
+            _ => false,
+        };
+        assert!(matched, "{}: unexpected error: {}", case, error);
+    }
+}
+
+#[test]
+fn log_start_offset_survives_a_crash() {
+    let vfs = SimulatedVfs::new();
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+    assert_eq!(write(&partition, 10), 10);
+    partition.delete_records_before(6).unwrap();
+
+    vfs.crash();
+    drop(partition);
+
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+    assert_eq!(partition.log_start_offset(), 6);
+    assert_eq!(partition.log_end_offset(), 10);
+    assert!(partition.read(5).is_err());
+    assert_eq!(partition.read(6).unwrap().value, value(6));
+}
+
+#[test]
+fn rolled_segments_survive_a_crash() {
+    const MESSAGES: usize = 2 * SEGMENT_MESSAGES + 1;
+
+    let vfs = SimulatedVfs::new();
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+    assert_eq!(write(&partition, MESSAGES), MESSAGES);
+
+    vfs.crash();
+    drop(partition);
+
+    assert_prefix(&vfs, MESSAGES, MESSAGES);
+}
+
+#[test]
+fn unsynced_directory_entries_are_dropped_on_crash() {
+    let vfs = SimulatedVfs::new();
+    vfs.create_dir_all(PATH).unwrap();
+    let synced = vfs.open("data/synced").unwrap();
+    synced.write_all_at(b"synced", 0).unwrap();
+    synced.sync_data().unwrap();
+    vfs.sync_dir(PATH).unwrap();
+
+    let unsynced = vfs.open("data/unsynced").unwrap();
+    unsynced.write_all_at(b"unsynced", 0).unwrap();
+    unsynced.sync_data().unwrap();
+    vfs.rename("data/synced", "data/renamed").unwrap();
+
+    vfs.crash();
+
+    let mut files = vfs.read_dir(PATH).unwrap();
+    files.sort();
+    assert_eq!(files, vec!["synced".to_string()]);
+    assert_eq!(vfs.open("data/synced").unwrap().size().unwrap(), 6);
+}
+
+#[test]
+fn failed_index_writes_do_not_fail_the_log() {
+    const MESSAGES: usize = 2 * SEGMENT_MESSAGES;
+
+    let vfs = SimulatedVfs::new();
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+    assert_eq!(write(&partition, 1), 1);
+
+    vfs.fail_files("00000000000000000000.timeindex", Some(Fault::Io));
+    assert_eq!(write(&partition, MESSAGES - 1), MESSAGES - 1);
+    let timestamp = partition.read(2).unwrap().timestamp;
+    assert_eq!(partition.offset_for_timestamp(timestamp).unwrap(), Some(2));
+
+    vfs.crash();
+    drop(partition);
+
+    let partition = assert_prefix(&vfs, MESSAGES, MESSAGES);
+    assert_eq!(partition.offset_for_timestamp(timestamp).unwrap(), Some(2));
+}
+
+// flip_byte damages the byte in the records of the log of the first segment at the given
+// position after the header of the batch that starts there.
+fn flip_byte(vfs: &SimulatedVfs, batch_position: usize) {
+    let log = vfs.open("data/00000000/00000000000000000000.log").unwrap();
+    let position = (batch_position + BATCH_HEADER_SIZE + 1) as u64;
+    let mut byte = [0u8];
+    log.read_exact_at(&mut byte, position).unwrap();
+    log.write_all_at(&[!byte[0]], position).unwrap();
+    log.sync_data().unwrap();
+}
+
+#[test]
+fn damaged_last_batch_is_truncated_as_torn() {
+    let vfs = SimulatedVfs::new();
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+    assert_eq!(write(&partition, 3), 3);
+    let last_position = partition.read_range_raw(2, 1, 1).unwrap()[0].position;
+    drop(partition);
+
+    flip_byte(&vfs, last_position);
+
+    let partition = assert_prefix(&vfs, 2, 2);
+    let report = partition.load_report();
+    assert_eq!(report.repaired.len(), 1);
+    assert!(report.repaired[0].truncated_bytes > 0);
+}
+
+#[test]
+fn damaged_batch_before_an_intact_one_fails_the_load() {
+    let vfs = SimulatedVfs::new();
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+    assert_eq!(write(&partition, 3), 3);
+    drop(partition);
+
+    flip_byte(&vfs, FILE_HEADER_SIZE);
+
+    let error = Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always))
+        .err()
+        .expect("the load fails");
+    assert!(matches!(
+        StorageError::of(&error),
+        Some(StorageError::CorruptRecord { .. })
+    ));
+
+    // nothing is truncated, so the damaged log is left for an investigation.
+    let log = vfs.open("data/00000000/00000000000000000000.log").unwrap();
+    assert!(log.size().unwrap() as usize > FILE_HEADER_SIZE + 2 * BATCH_HEADER_SIZE);
+}
+
+// write_truncated_log writes a log, in which offset 2 is in the middle of the first batch
+// and there is a segment after the one with it.
+fn write_truncated_log(vfs: &SimulatedVfs) -> Partition {
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(vfs, DurabilityPolicy::Always)).unwrap();
+    partition
+        .write_batch(
+            (0..3)
+                .map(|offset| (Utc::now(), None, value(offset), vec![]))
+                .collect(),
+        )
+        .unwrap();
+    assert_eq!(write(&partition, SEGMENT_MESSAGES), SEGMENT_MESSAGES);
+    partition
+}
+
+#[test]
+fn truncation_in_the_middle_of_a_batch_survives_a_crash() {
+    let vfs = SimulatedVfs::new();
+    let partition = write_truncated_log(&vfs);
+
+    partition.truncate_to(2).unwrap();
+    assert_eq!(partition.log_end_offset(), 2);
+    assert_eq!(partition.read(1).unwrap().value, value(1));
+    assert!(partition.read(2).is_err());
+
+    vfs.crash();
+    drop(partition);
+
+    let partition = assert_prefix(&vfs, 2, 2);
+    assert_eq!(partition.log_end_offset(), 3);
+}
+
+#[test]
+fn interrupted_truncation_keeps_the_messages_before_the_offset() {
+    const MESSAGES: usize = 3 + SEGMENT_MESSAGES;
+
+    for tear_at in 0.. {
+        let vfs = SimulatedVfs::new();
+        let partition = write_truncated_log(&vfs);
+
+        vfs.tear_write_at(tear_at);
+        let truncated = partition.truncate_to(2);
+        if !vfs.is_halted() {
+            truncated.unwrap();
+            break;
+        }
+
+        assert!(truncated.is_err());
+        vfs.crash();
+        drop(partition);
+
+        assert_prefix(&vfs, 2, MESSAGES);
+    }
+}
+
+/// BASELINE_FILES are the files of a partition with 7 messages and 3 messages per segment
+/// written by the first version of the storage.
+const BASELINE_FILES: [(&str, &[u8]); 9] = [
+    (
+        "00000000.log",
+        include_bytes!("../../tests/fixtures/baseline/00000000/00000000.log"),
+    ),
+    (
+        "00000000.index",
+        include_bytes!("../../tests/fixtures/baseline/00000000/00000000.index"),
+    ),
+    (
+        "00000000.timeindex",
+        include_bytes!("../../tests/fixtures/baseline/00000000/00000000.timeindex"),
+    ),
+    (
+        "00000001.log",
+        include_bytes!("../../tests/fixtures/baseline/00000000/00000001.log"),
+    ),
+    (
+        "00000001.index",
+        include_bytes!("../../tests/fixtures/baseline/00000000/00000001.index"),
+    ),
+    (
+        "00000001.timeindex",
+        include_bytes!("../../tests/fixtures/baseline/00000000/00000001.timeindex"),
+    ),
+    (
+        "00000002.log",
+        include_bytes!("../../tests/fixtures/baseline/00000000/00000002.log"),
+    ),
+    (
+        "00000002.index",
+        include_bytes!("../../tests/fixtures/baseline/00000000/00000002.index"),
+    ),
+    (
+        "00000002.timeindex",
+        include_bytes!("../../tests/fixtures/baseline/00000000/00000002.timeindex"),
+    ),
+];
+const BASELINE_MESSAGES: usize = 7;
+
+// write_baseline writes the baseline partition with the given changes of its files
+// and makes it durable.
+fn write_baseline(vfs: &SimulatedVfs, change: impl Fn(&str, &mut Vec<u8>)) {
+    vfs.create_dir_all("data/00000000").unwrap();
+    for (name, data) in BASELINE_FILES {
+        let mut data = data.to_vec();
+        change(name, &mut data);
+
+        let file = vfs.open(&format!("data/00000000/{}", name)).unwrap();
+        file.write_all_at(&data, 0).unwrap();
+        file.sync_data().unwrap();
+    }
+    vfs.sync_dir("data/00000000").unwrap();
+}
+
+// assert_baseline checks that the partition starts with the given amount of messages
+// written by the baseline.
+fn assert_baseline(partition: &Partition, messages: usize) {
+    assert_eq!(partition.log_start_offset(), 0);
+    assert!(partition.log_end_offset() >= messages);
+    for offset in 0..messages {
+        let message = partition.read(offset).unwrap();
+        assert_eq!(message.offset, offset);
+        assert_eq!(message.value, format!("message {}", offset).into_bytes());
+        assert_eq!(
+            message.key,
+            (offset % 2 == 0).then(|| format!("key {}", offset).into_bytes())
+        );
+        assert_eq!(message.timestamp.timestamp(), 1_700_000_000 + offset as i64);
+    }
+}
+
+#[test]
+fn baseline_partition_is_upgraded() {
+    let vfs = SimulatedVfs::new();
+    write_baseline(&vfs, |_, _| {});
+
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+    let report = partition.load_report();
+    assert_eq!(report.upgraded.len(), 3);
+    assert_eq!(
+        report.upgraded.iter().map(|r| r.records).sum::<usize>(),
+        BASELINE_MESSAGES
+    );
+    assert_baseline(&partition, BASELINE_MESSAGES);
+    assert_eq!(partition.log_end_offset(), BASELINE_MESSAGES);
+
+    let mut files = vfs.read_dir("data/00000000").unwrap();
+    files.sort();
+    assert_eq!(
+        files,
+        [0, 3, 6]
+            .iter()
+            .flat_map(|offset| ["index", "log", "timeindex"]
+                .map(|extension| format!("{:020}.{}", offset, extension)))
+            .collect::<Vec<_>>()
+    );
+
+    assert_eq!(write(&partition, 1), 1);
+    drop(partition);
+
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+    assert!(partition.load_report().is_clean());
+    assert_baseline(&partition, BASELINE_MESSAGES);
+    assert_eq!(partition.log_end_offset(), BASELINE_MESSAGES + 1);
+}
+
+#[test]
+fn baseline_torn_tail_is_dropped() {
+    let vfs = SimulatedVfs::new();
+    write_baseline(&vfs, |name, data| {
+        if name == "00000002.log" {
+            data.truncate(data.len() - 3);
+        }
+    });
+
+    let partition =
+        Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+    assert_baseline(&partition, BASELINE_MESSAGES - 1);
+    assert_eq!(partition.log_end_offset(), BASELINE_MESSAGES - 1);
+    let report = partition.load_report();
+    assert_eq!(report.upgraded[2].records, 0);
+    assert!(report.upgraded[2].dropped_bytes > 0);
+}
+
+#[test]
+fn baseline_corruption_in_a_closed_segment_fails_the_upgrade() {
+    let vfs = SimulatedVfs::new();
+    write_baseline(&vfs, |name, data| {
+        if name == "00000001.log" {
+            // the size of the second record is far beyond the end of the log.
+            let second = 8 + u64::from_le_bytes(data[..8].try_into().unwrap()) as usize;
+            data[second + 7] = 0x7f;
+        }
+    });
+
+    let error = Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always))
+        .err()
+        .expect("the load fails");
+    assert_eq!(error.kind(), ErrorKind::InvalidData);
+    assert!(vfs.exists("data/00000000/00000001.log"));
+    assert!(vfs.exists("data/00000000/00000002.log"));
+}
+
+#[test]
+fn interrupted_baseline_upgrade_is_repeated() {
+    for tear_at in 0.. {
+        let vfs = SimulatedVfs::new();
+        write_baseline(&vfs, |_, _| {});
+
+        vfs.tear_write_at(tear_at);
+        let loaded = Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always));
+        if !vfs.is_halted() {
+            assert_eq!(loaded.unwrap().log_end_offset(), BASELINE_MESSAGES);
+            break;
+        }
+
+        assert!(loaded.is_err());
+        vfs.crash();
+
+        let partition =
+            Partition::new(PATH.to_string(), 0, config(&vfs, DurabilityPolicy::Always)).unwrap();
+        assert_baseline(&partition, BASELINE_MESSAGES);
+        assert_eq!(partition.log_end_offset(), BASELINE_MESSAGES);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{
+    fs::File,
+    io::{Error, Write},
+    marker::PhantomData,
+    ops::Deref,
+    sync::Arc,
+};
+
+use memmap2::{Mmap, MmapOptions};
+
+use super::vfs::VfsFile;
+
+/// FileRegion is a range of a segment's log, which holds whole batches as they are stored
+/// on the disk.
+///
+/// It is meant to be sent to a socket without decoding the batches, either with sendfile
+/// or splice from the file at the region's position, or by writing the mapped memory.
+pub struct FileRegion {
+    file: Arc<dyn VfsFile>,
+    /// position is the position of the first batch in the log.
+    pub position: usize,
+    /// len is the size of the region in bytes.
+    pub len: usize,
+    /// records is the amount of messages in the batches of the region.
+    pub records: usize,
+}
+
+impl FileRegion {
+    pub(super) fn new(file: Arc<dyn VfsFile>, position: usize, len: usize, records: usize) -> Self {
+        Self {
+            file,
+            position,
+            len,
+            records,
+        }
+    }
+
+    /// Returns the log file, which holds the region, or None if the log is not stored
+    /// on the real file system.
+    ///
+    /// The file is shared, so it must only be read at the given positions,
+    /// e.g. with sendfile or pread, and never seeked.
+    pub fn file(&self) -> Option<&File> {
+        self.file.as_file()
+    }
+
+    /// Maps the region into the memory, the mapping could not outlive the region.
+    ///
+    /// The region of a log, which is not stored on the real file system, is read instead.
+    pub fn map(&self) -> Result<MappedRegion<'_>, Error> {
+        let Some(file) = self.file.as_file() else {
+            let mut buffer = vec![0u8; self.len];
+            self.file.read_exact_at(&mut buffer, self.position as u64)?;
+
+            return Ok(MappedRegion {
+                memory: Memory::Read(buffer),
+                region: PhantomData,
+            });
+        };
+
+        // SAFETY: the batches in the region are never changed, since the log is only appended to
+        // and truncated either when a partition is loaded, before any region could be taken,
+        // or by Segment::truncate_to, which refuses to do that while any region of the log
+        // exists. The mapping borrows the region, so the region exists while it is used.
+        let map = unsafe {
+            MmapOptions::new()
+                .offset(self.position as u64)
+                .len(self.len)
+                .map(file)
+        }?;
+
+        Ok(MappedRegion {
+            memory: Memory::Mapped(map),
+            region: PhantomData,
+        })
+    }
+
+    /// Writes the batches of the region to the given writer.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&self.map()?)
+    }
+}
+
+/// MappedRegion is the memory, into which a FileRegion is mapped.
+pub struct MappedRegion<'a> {
+    memory: Memory,
+    region: PhantomData<&'a FileRegion>,
+}
+
+/// Memory holds the bytes of a MappedRegion.
+enum Memory {
+    Mapped(Mmap),
+    Read(Vec<u8>),
+}
+
+impl Deref for MappedRegion<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match &self.memory {
+            Memory::Mapped(map) => map,
+            Memory::Read(buffer) => buffer,
+        }
+    }
+}
@@ -0,0 +1,138 @@
+use core::fmt;
+use std::{
+    cmp, fs,
+    io::{Error, ErrorKind, Read, Write},
+    path::PathBuf,
+};
+
+use super::vfs::VfsFile;
+
+/// COPY_BUFFER_SIZE is the size of the chunks, in which the files are copied
+/// to and from the storage.
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// RemoteStorage is a secondary store, to which the closed segments of the partitions
+/// are copied, so that their local copies could be deleted from the fast disk.
+///
+/// It stores whole files as objects under the keys, which are relative paths separated by `/`.
+/// An object must either be stored whole or not at all, so an interrupted upload is never seen.
+pub trait RemoteStorage: fmt::Debug + Send + Sync {
+    /// Stores the content of the local file under the key, replacing the object that is there.
+    fn upload(&self, source: &dyn VfsFile, key: &str) -> Result<(), Error>;
+
+    /// Writes the object stored under the key into the local file, replacing its content.
+    /// The file is not synced.
+    fn download(&self, key: &str, destination: &dyn VfsFile) -> Result<(), Error>;
+
+    /// Returns the keys of the stored objects, which start with the prefix.
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error>;
+
+    /// Deletes the object stored under the key, if there is one.
+    fn delete(&self, key: &str) -> Result<(), Error>;
+}
+
+/// LocalDirectoryStorage keeps the objects as files in a local directory, f.e. on a slower
+/// disk or on a mounted network file system.
+#[derive(Debug)]
+pub struct LocalDirectoryStorage {
+    root: PathBuf,
+}
+
+impl LocalDirectoryStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self, Error> {
+        let root = root.into();
+        fs::create_dir_all(&root)?;
+
+        Ok(Self { root })
+    }
+
+    // object_path returns the path of the object's file, the keys must not leave the root.
+    fn object_path(&self, key: &str) -> Result<PathBuf, Error> {
+        if key.is_empty() || key.split('/').any(|part| part.is_empty() || part == "..") {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid object key `{}`", key),
+            ));
+        }
+
+        Ok(self.root.join(key))
+    }
+}
+
+impl RemoteStorage for LocalDirectoryStorage {
+    /// The file is copied next to the object and renamed over it after it is synced.
+    fn upload(&self, source: &dyn VfsFile, key: &str) -> Result<(), Error> {
+        let path = self.object_path(key)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let tmp_path = PathBuf::from(format!("{}.upload", path.display()));
+        let mut tmp = fs::File::create(&tmp_path)?;
+        let size = source.size()?;
+        let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+        let mut position = 0;
+        while position < size {
+            let len = cmp::min(COPY_BUFFER_SIZE as u64, size - position) as usize;
+            source.read_exact_at(&mut buffer[..len], position)?;
+            tmp.write_all(&buffer[..len])?;
+            position += len as u64;
+        }
+
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &path)
+    }
+
+    fn download(&self, key: &str, destination: &dyn VfsFile) -> Result<(), Error> {
+        let mut object = fs::File::open(self.object_path(key)?)?;
+        destination.set_len(0)?;
+
+        let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+        let mut position = 0;
+        loop {
+            let len = object.read(&mut buffer)?;
+            if len == 0 {
+                return Ok(());
+            }
+            destination.write_all_at(&buffer[..len], position)?;
+            position += len as u64;
+        }
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+        let mut keys = Vec::new();
+        let mut dirs = vec![self.root.clone()];
+
+        while let Some(dir) = dirs.pop() {
+            for entry in fs::read_dir(&dir)? {
+                let entry_path = entry?.path();
+                if entry_path.is_dir() {
+                    dirs.push(entry_path);
+                    continue;
+                }
+
+                let Some(key) = entry_path
+                    .strip_prefix(&self.root)
+                    .ok()
+                    .and_then(|key| key.to_str())
+                else {
+                    continue;
+                };
+                // the objects, which are being uploaded, are not stored yet.
+                if key.starts_with(prefix) && !key.ends_with(".upload") {
+                    keys.push(key.to_string());
+                }
+            }
+        }
+
+        keys.sort();
+        Ok(keys)
+    }
+
+    fn delete(&self, key: &str) -> Result<(), Error> {
+        match fs::remove_file(self.object_path(key)?) {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
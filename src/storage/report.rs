@@ -0,0 +1,73 @@
+use core::fmt;
+
+use super::format::FORMAT_VERSION;
+
+/// LoadReport describes what was done to the segments of a partition, when it was loaded.
+#[derive(Default)]
+pub struct LoadReport {
+    /// upgraded are the segments that were upgraded to the current format version.
+    pub upgraded: Vec<UpgradeReport>,
+    /// repaired are the segments that were left inconsistent and had to be repaired.
+    pub repaired: Vec<RecoveryReport>,
+}
+
+impl LoadReport {
+    /// Returns true if nothing had to be upgraded or repaired.
+    pub fn is_clean(&self) -> bool {
+        self.upgraded.is_empty() && self.repaired.is_empty()
+    }
+}
+
+/// UpgradeReport describes what was done to a segment written by the first version
+/// of the storage on load.
+pub struct UpgradeReport {
+    /// segment is the path of the legacy log.
+    pub segment: String,
+    /// records is the amount of records moved to the upgraded log.
+    pub records: usize,
+    /// dropped_bytes is the amount of bytes at the end of the old log, which were not
+    /// valid records and were left out of the upgraded one.
+    pub dropped_bytes: usize,
+}
+
+impl fmt::Display for UpgradeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[Upgrade of `{}` to version {}: Records: {}, Dropped: {} bytes]",
+            self.segment, FORMAT_VERSION, self.records, self.dropped_bytes
+        )
+    }
+}
+
+/// RecoveryReport describes what was repaired in a segment on load.
+pub struct RecoveryReport {
+    pub segment: String,
+    /// records is the amount of valid records left in the log.
+    pub records: usize,
+    /// truncated_bytes is the amount of bytes cut from the end of the log.
+    pub truncated_bytes: usize,
+    pub offset_index_rebuilt: bool,
+    pub time_index_rebuilt: bool,
+}
+
+impl RecoveryReport {
+    /// Returns true if nothing had to be repaired.
+    pub fn is_clean(&self) -> bool {
+        self.truncated_bytes == 0 && !self.offset_index_rebuilt && !self.time_index_rebuilt
+    }
+}
+
+impl fmt::Display for RecoveryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[Recovery of {}: Records: {}, Truncated: {} bytes, OffsetIndexRebuilt: {}, TimeIndexRebuilt: {}]",
+            self.segment,
+            self.records,
+            self.truncated_bytes,
+            self.offset_index_rebuilt,
+            self.time_index_rebuilt,
+        )
+    }
+}
@@ -1,197 +1,958 @@
 use core::fmt;
 use std::{
-    cell::RefCell,
-    fs::{self, File, OpenOptions},
-    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
-    os::unix::fs::MetadataExt,
+    cmp,
+    io::{Error, ErrorKind},
+    iter::Peekable,
+    mem,
+    sync::Arc,
+    time::Duration,
 };
 
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, TimeZone, Utc};
 
 use crate::core::message::Message;
 
-use super::{offset_index::OffsetIndex, timestamp_index::TimestampIndex};
+use super::{
+    compression::{Compression, CompressionStats},
+    error::StorageError,
+    format::{self, BatchBuilder, BatchHeader, FileKind, BATCH_HEADER_SIZE, FILE_HEADER_SIZE},
+    offset_index::OffsetIndex,
+    region::FileRegion,
+    report::RecoveryReport,
+    timestamp_index::TimestampIndex,
+    vfs::{Vfs, VfsFile},
+};
+
+pub const LOG_EXTENSION: &str = "log";
+pub const OFFSET_INDEX_EXTENSION: &str = "index";
+pub const TIME_INDEX_EXTENSION: &str = "timeindex";
+/// SEGMENT_EXTENSIONS are the extensions of all of the files that belong to a segment.
+pub const SEGMENT_EXTENSIONS: [&str; 3] =
+    [LOG_EXTENSION, OFFSET_INDEX_EXTENSION, TIME_INDEX_EXTENSION];
+
+/// INDEX_INTERVAL_BYTES is the amount of log bytes written between two entries of the offset index.
+const INDEX_INTERVAL_BYTES: usize = 4096;
+
+/// MAX_BATCH_BYTES is the size of the records before the compression, after reaching which
+/// the rest of a write goes to the next batch.
+const MAX_BATCH_BYTES: usize = 1024 * 1024;
+
+/// COPY_BUFFER_SIZE is the amount of bytes copied at once, when the batches of a log
+/// are copied to another one as they are.
+const COPY_BUFFER_SIZE: usize = 64 * 1024;
+
+/// SegmentLimits are the limits, after reaching which a segment takes no more messages.
+pub struct SegmentLimits {
+    /// max_messages is the max amount of messages in the segment.
+    pub max_messages: usize,
+    /// max_bytes is the max size of the log in bytes.
+    pub max_bytes: usize,
+    /// max_age is the max difference between the timestamps of the first message in the segment
+    /// and a new one.
+    pub max_age: Option<Duration>,
+}
+
+impl SegmentLimits {
+    /// Returns the limits that never stop a segment from taking more messages.
+    pub fn unlimited() -> Self {
+        Self {
+            max_messages: usize::MAX,
+            max_bytes: usize::MAX,
+            max_age: None,
+        }
+    }
+
+    fn is_too_old(&self, first: Option<DateTime<Utc>>, timestamp: DateTime<Utc>) -> bool {
+        let (Some(max_age), Some(first)) = (self.max_age, first) else {
+            return false;
+        };
+
+        match (timestamp - first).to_std() {
+            Ok(age) => age >= max_age,
+            Err(_) => false,
+        }
+    }
+}
+
+/// WrittenBatch describes the messages written by Segment::write_batch.
+pub struct WrittenBatch {
+    /// messages is the amount of written messages.
+    pub messages: usize,
+    pub compression: CompressionStats,
+}
+
+/// RangeRead collects the messages of a range read until any of its limits is reached.
+///
+/// The log is read by whole batches, so the bytes are counted per batch.
+/// The first batch is always taken, even if it is bigger than the byte limit,
+/// so that a big message could still be read. The read stops at the first batch that is not
+/// taken, so that a smaller batch after it could not leave a gap in the returned messages.
+pub struct RangeRead {
+    /// messages are the decoded messages, they are left empty by the raw reads.
+    pub messages: Vec<Message>,
+    /// records is the amount of read messages.
+    pub records: usize,
+    /// bytes is the size of the read batches in the log.
+    pub bytes: usize,
+    max_messages: usize,
+    max_bytes: usize,
+    /// stopped tells that a batch was not taken, so the read is over.
+    stopped: bool,
+}
+
+impl RangeRead {
+    pub fn new(max_messages: usize, max_bytes: usize) -> Self {
+        Self {
+            messages: Vec::new(),
+            records: 0,
+            bytes: 0,
+            max_messages,
+            max_bytes,
+            stopped: false,
+        }
+    }
+
+    /// Returns true if no more records could be taken.
+    pub fn is_full(&self) -> bool {
+        self.stopped || self.records >= self.max_messages || self.bytes >= self.max_bytes
+    }
+
+    /// Counts the batch of the given size if it fits into the limits and tells whether it was taken,
+    /// the read is full after a batch is not taken.
+    pub fn take_batch(&mut self, batch_size: usize) -> bool {
+        if self.stopped
+            || self.records >= self.max_messages
+            || (self.bytes > 0 && self.bytes + batch_size > self.max_bytes)
+        {
+            self.stopped = true;
+            return false;
+        }
+
+        self.bytes += batch_size;
+        true
+    }
+
+    /// Adds the message of a taken batch if it fits into the limit of messages
+    /// and tells whether it was taken.
+    pub fn take_message(&mut self, message: Message) -> bool {
+        if self.records >= self.max_messages {
+            return false;
+        }
+
+        self.records += 1;
+        self.messages.push(message);
+        true
+    }
+}
 
 pub struct Segment {
+    /// vfs is the file system, on which the files of the segment are stored.
+    vfs: Arc<dyn Vfs>,
     base_path: String,
-    number: i32,
-    log_path: String,
-    range: (usize, usize),
-
-    log: RefCell<File>,
+    /// base_offset is the offset of the first message in this segment, the files of the segment
+    /// are named after it.
+    base_offset: usize,
+
+    /// next_offset is the offset that will be given to the next message written to this segment.
+    next_offset: usize,
+    /// log_size is the size of the log file in bytes, i.e. the position of the next batch.
+    log_size: usize,
+    /// bytes_since_last_index is the amount of bytes written after the last offset index entry.
+    bytes_since_last_index: usize,
+    /// first_timestamp is the timestamp of the first message in this segment.
+    first_timestamp: Option<DateTime<Utc>>,
+
+    /// log is written and read only at the given positions without moving the cursor,
+    /// so the reads need only a shared reference and could run concurrently.
+    /// It is shared with the file regions, which only read it.
+    log: Arc<dyn VfsFile>,
     offset_index: OffsetIndex,
     time_index: TimestampIndex,
 }
 
 impl Segment {
-    pub fn new(path: String, number: i32, range: (usize, usize)) -> Result<Self, Error> {
-        let log_path = format!("{}/{:08}.log", path, number);
-        let offset_index_path = format!("{}/{:08}.index", path, number);
-        let time_index_path = format!("{}/{:08}.timeindex", path, number);
+    pub fn new(vfs: Arc<dyn Vfs>, path: String, base_offset: usize) -> Result<Self, Error> {
+        let mut segment = Self::open(vfs, path, base_offset)?;
+        segment.load_tail()?;
+
+        Ok(segment)
+    }
+
+    /// Opens the segment and validates its log batch by batch.
+    ///
+    /// A torn batch and everything after it is truncated from the log, and both indexes are
+    /// rebuilt if they do not match the batches that are left. A batch is considered torn
+    /// if its header could not be decoded, if it does not fit into the log, or if it does not
+    /// match its checksum and is not followed by an intact batch. A batch that does not match
+    /// its checksum, but is followed by an intact one, could not be the result of a torn write,
+    /// so it fails the recovery with StorageError::CorruptRecord and nothing is truncated.
+    pub fn recover(
+        vfs: Arc<dyn Vfs>,
+        path: String,
+        base_offset: usize,
+    ) -> Result<(Self, RecoveryReport), Error> {
+        let mut segment = Self::open(vfs, path, base_offset)?;
+        let report = segment.recover_log()?;
+
+        Ok((segment, report))
+    }
+
+    /// Returns the name of the segment's file with the given extension.
+    pub fn file_name(base_offset: usize, extension: &str) -> String {
+        format!("{:020}.{}", base_offset, extension)
+    }
+
+    /// Parses the base offset from the name of a segment's file without the extension.
+    pub fn parse_base_offset(stem: &str) -> Option<usize> {
+        if stem.len() != 20 || !stem.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        stem.parse().ok()
+    }
 
-        let log_file = Self::open_log_file(log_path.clone())?;
-        let offset_index = OffsetIndex::new(offset_index_path)?;
-        let time_index = TimestampIndex::new(time_index_path)?;
+    /// Deletes the files of the segment.
+    ///
+    /// The indexes are deleted before the log, so an interrupted deletion leaves a log without
+    /// the indexes, which are rebuilt on load, and never the indexes without the log.
+    pub fn delete(&self) -> Result<(), Error> {
+        for extension in [OFFSET_INDEX_EXTENSION, TIME_INDEX_EXTENSION, LOG_EXTENSION] {
+            match self.vfs.remove_file(&Self::file_path(
+                &self.base_path,
+                self.base_offset,
+                extension,
+            )) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => continue,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Moves the files of the segment with the given base offset from one directory to another,
+    /// replacing the files that are already there.
+    ///
+    /// The old indexes are deleted first and the new ones are moved after the log,
+    /// so if it is interrupted, the log is left without the indexes, which are rebuilt on load.
+    pub fn replace_files(
+        vfs: &dyn Vfs,
+        from: &str,
+        to: &str,
+        base_offset: usize,
+    ) -> Result<(), Error> {
+        for extension in [OFFSET_INDEX_EXTENSION, TIME_INDEX_EXTENSION] {
+            match vfs.remove_file(&Self::file_path(to, base_offset, extension)) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+                _ => continue,
+            }
+        }
+
+        for extension in [LOG_EXTENSION, OFFSET_INDEX_EXTENSION, TIME_INDEX_EXTENSION] {
+            vfs.rename(
+                &Self::file_path(from, base_offset, extension),
+                &Self::file_path(to, base_offset, extension),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the path of the segment's file with the given extension in the given directory.
+    pub fn file_path(path: &str, base_offset: usize, extension: &str) -> String {
+        format!("{}/{}", path, Self::file_name(base_offset, extension))
+    }
+
+    fn open(vfs: Arc<dyn Vfs>, path: String, base_offset: usize) -> Result<Self, Error> {
+        let log_path = Self::file_path(&path, base_offset, LOG_EXTENSION);
+        let offset_index_path = Self::file_path(&path, base_offset, OFFSET_INDEX_EXTENSION);
+        let time_index_path = Self::file_path(&path, base_offset, TIME_INDEX_EXTENSION);
+
+        let log = vfs.open(&log_path)?;
+        format::version_guard(log.as_ref(), FileKind::Log, &log_path)?;
+        let offset_index = OffsetIndex::new(vfs.as_ref(), offset_index_path)?;
+        let time_index = TimestampIndex::new(vfs.as_ref(), time_index_path)?;
+
+        let log_size = log.size()? as usize;
 
         Ok(Self {
+            vfs,
             base_path: path,
-            number,
-            log_path,
-            range,
-            log: RefCell::new(log_file),
+            base_offset,
+            next_offset: base_offset,
+            log_size,
+            bytes_since_last_index: 0,
+            first_timestamp: None,
+            log,
             offset_index,
             time_index,
         })
     }
 
-    pub fn write(&mut self, message: Message) -> Result<(), Error> {
-        self.offset_range_guard(message.offset)?;
+    /// Appends the messages to the log with a single write and adds the entries for them
+    /// to the indexes, also with one write per index.
+    ///
+    /// Messages are taken while they fit into the given limits and are grouped into batches
+    /// of about MAX_BATCH_BYTES, each of which is compressed as a whole with the given codec.
+    /// The byte limit is checked against the size of the batches before the compression.
+    /// An empty segment always takes at least one message,
+    /// so that a message bigger than the limit could still be written.
+    pub fn write_batch<I>(
+        &mut self,
+        messages: &mut Peekable<I>,
+        limits: &SegmentLimits,
+        compression: Compression,
+    ) -> Result<WrittenBatch, Error>
+    where
+        I: Iterator<Item = Message>,
+    {
+        let mut batches = Vec::new();
+        let mut batch = BatchBuilder::default();
+        // raw_log_size is the size the log would have if the batches were not compressed.
+        let mut raw_log_size = self.log_size;
+        let mut next_offset = self.next_offset;
+        let mut first_timestamp = self.first_timestamp;
+
+        while let Some(message) = messages.peek() {
+            self.offset_guard(message.offset, next_offset)?;
+
+            if message.offset - self.base_offset >= limits.max_messages {
+                break;
+            }
+
+            let is_empty = raw_log_size == FILE_HEADER_SIZE;
+            if !is_empty && limits.is_too_old(first_timestamp, message.timestamp) {
+                break;
+            }
+
+            if batch.records_size() >= MAX_BATCH_BYTES {
+                batches.push(mem::take(&mut batch));
+            }
+
+            let record = batch.encode(message)?;
+            let mut record_size = record.len();
+            if batch.is_empty() {
+                record_size += BATCH_HEADER_SIZE;
+            }
+            if !is_empty && raw_log_size + record_size > limits.max_bytes {
+                break;
+            }
+
+            batch.push(message, &record)?;
+            raw_log_size += record_size;
+            next_offset = message.offset + 1;
+            first_timestamp = first_timestamp.or(Some(message.timestamp));
+
+            messages.next();
+        }
+        if !batch.is_empty() {
+            batches.push(batch);
+        }
+
+        let mut buffer = Vec::new();
+        let mut offset_entries = Vec::new();
+        let mut time_entries = Vec::new();
+        let mut bytes_since_last_index = self.bytes_since_last_index;
+        let mut written = WrittenBatch {
+            messages: 0,
+            compression: CompressionStats::default(),
+        };
+
+        for batch in batches {
+            let raw_bytes = batch.records_size();
+            let (header, data) = batch.build(compression)?;
+
+            if bytes_since_last_index >= INDEX_INTERVAL_BYTES {
+                offset_entries.push((header.base_offset, self.log_size + buffer.len()));
+                bytes_since_last_index = 0;
+            }
+            time_entries.push((
+                Utc.timestamp_nanos(header.max_timestamp),
+                header.base_offset,
+            ));
+
+            bytes_since_last_index += data.len();
+            written.messages += header.records;
+            written.compression += CompressionStats {
+                raw_bytes,
+                stored_bytes: header.size,
+            };
+
+            buffer.extend(data);
+        }
+
+        if buffer.is_empty() {
+            return Ok(written);
+        }
+
+        self.log.write_all_at(&buffer, self.log_size as u64)?;
 
-        let logical_offset = message.offset;
-        let physical_offset = fs::metadata(self.log_path.clone()).unwrap().size() as usize;
-        let timestamp = message.timestamp;
+        self.log_size += buffer.len();
+        self.bytes_since_last_index = bytes_since_last_index;
+        self.next_offset = next_offset;
+        self.first_timestamp = first_timestamp;
 
-        let data = Self::serialize_message(message)?;
-        let header = Header::serialize(Header { size: data.len() })?;
+        // the batches are already in the log, so a failed write of an index does not fail
+        // the write: the index keeps its entries in the memory and its file is removed,
+        // so that it is rebuilt from the log on load.
+        let indexed = [
+            (
+                self.offset_index.append(&offset_entries),
+                OFFSET_INDEX_EXTENSION,
+            ),
+            (self.time_index.append(&time_entries), TIME_INDEX_EXTENSION),
+        ];
+        for (result, extension) in indexed {
+            if result.is_err() {
+                self.remove_index_file(extension);
+            }
+        }
 
-        let mut file = self.log.borrow_mut();
+        Ok(written)
+    }
 
-        file.seek(SeekFrom::End(0))?;
-        file.write_all(&header)?;
-        file.write_all(&data)?;
-        file.flush()?;
+    /// Writes the indexes, which were removed after a failed write, to the new files,
+    /// so the segment could be copied whole.
+    pub fn restore_indexes(&mut self) -> Result<(), Error> {
+        let offset_index_path =
+            Self::file_path(&self.base_path, self.base_offset, OFFSET_INDEX_EXTENSION);
+        let time_index_path =
+            Self::file_path(&self.base_path, self.base_offset, TIME_INDEX_EXTENSION);
+
+        let restored = self
+            .offset_index
+            .reattach(self.vfs.as_ref(), &offset_index_path)?
+            | self
+                .time_index
+                .reattach(self.vfs.as_ref(), &time_index_path)?;
+        match restored {
+            true => self.vfs.sync_dir(&self.base_path),
+            false => Ok(()),
+        }
+    }
 
-        self.offset_index.write(logical_offset, physical_offset)?;
-        self.time_index.write(timestamp, logical_offset)
+    // remove_index_file removes the file of the index that is behind its entries.
+    // It is done on a best-effort basis, since the index in the memory is still valid,
+    // and if the file is left, it only makes the lookups on load slower.
+    fn remove_index_file(&self, extension: &str) {
+        let path = Self::file_path(&self.base_path, self.base_offset, extension);
+        if self.vfs.remove_file(&path).is_ok() {
+            let _ = self.vfs.sync_dir(&self.base_path);
+        }
     }
 
     pub fn read(&self, offset: usize) -> Result<Message, Error> {
-        self.offset_range_guard(offset)?;
+        if !self.belongs_to_segment(offset) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("given offset({}) is not stored in {}", offset, self),
+            ));
+        }
 
-        let physical_offset = self.offset_index.read(offset)?;
-        self.log
-            .borrow_mut()
-            .seek(SeekFrom::Start(physical_offset as u64))?;
+        let position = self.start_position(offset)?;
+        if let Some((messages, _)) = self.read_batch(position)? {
+            if let Some(message) = messages.into_iter().find(|m| m.offset == offset) {
+                return Ok(message);
+            }
+        }
 
-        let header = self.read_header(None)?;
-        let mut buffer = vec![0u8; header.size];
-        self.log.borrow_mut().read(&mut buffer)?;
+        Err(Error::new(
+            ErrorKind::NotFound,
+            format!("message with offset {} is not found in {}", offset, self),
+        ))
+    }
 
-        Self::deserialize_message(&buffer)
+    /// Returns the offset of the first message in this segment with the timestamp at or after
+    /// the given one, or None if all of the messages in this segment are older.
+    ///
+    /// The time index points at the batch with such a message, which is read to find it.
+    pub fn offset_for_timestamp(&self, timestamp: DateTime<Utc>) -> Result<Option<usize>, Error> {
+        let Some(base_offset) = self.time_index.lookup(timestamp) else {
+            return Ok(None);
+        };
+
+        let position = self.start_position(base_offset)?;
+        let Some((messages, _)) = self.read_batch(position)? else {
+            return Ok(None);
+        };
+
+        Ok(messages
+            .iter()
+            .find(|m| m.timestamp >= timestamp)
+            .map(|m| m.offset))
     }
 
     pub fn read_by_timestamp(&self, timestamp: DateTime<Utc>) -> Result<Message, Error> {
-        let offset = self.time_index.read(timestamp)?;
-        self.read(offset)
+        match self.offset_for_timestamp(timestamp)? {
+            Some(offset) => self.read(offset),
+            None => Err(Error::new(
+                ErrorKind::NotFound,
+                format!("no message at or after {} in {}", timestamp, self),
+            )),
+        }
     }
 
-    pub fn size(&self) -> Result<usize, Error> {
-        let offset_size = self.offset_index.size()?;
-        let timestamp_size = self.time_index.size()?;
+    /// Removes the batches with the messages at or after the given offset from this segment,
+    /// so that the next written message gets this offset. The offset has to be at the start
+    /// of a batch, Segment::write_truncated_copy is used for the rest of them.
+    ///
+    /// The entries of both indexes for the removed batches are cut and synced before the log
+    /// is truncated, so an interrupted truncation never leaves the indexes pointing beyond it.
+    /// The log could not be truncated while any of its regions exists.
+    pub fn truncate_to(&mut self, offset: usize) -> Result<(), Error> {
+        let position = self.start_position(offset)?;
+        if position < self.log_size {
+            let header = self.read_batch_header(position)?;
+            if header.base_offset < offset {
+                return Err(Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("offset {} is in the middle of a batch in {}", offset, self),
+                ));
+            }
 
-        if offset_size != timestamp_size {
-            return Err(Error::new(
-                ErrorKind::Other,
-                "offset index size is different than timestamp index size",
-            ));
+            self.offset_index.truncate(position)?;
+            self.time_index.truncate(header.base_offset)?;
+            self.offset_index.sync()?;
+            self.time_index.sync()?;
+
+            self.log.set_len(position as u64)?;
+            self.log.sync_data()?;
+
+            self.log_size = position;
+            self.bytes_since_last_index = match self.offset_index.last() {
+                Some((_, physical)) => position - physical,
+                None => position - FILE_HEADER_SIZE,
+            };
+            self.first_timestamp = self.read_first_timestamp()?;
+        }
+        self.next_offset = offset;
+
+        Ok(())
+    }
+
+    /// Writes a synced copy of this segment without the messages at or after the given offset
+    /// into the given directory, if the offset is in the middle of a batch, and tells whether
+    /// it was written. Otherwise the segment could be truncated by Segment::truncate_to.
+    ///
+    /// The batches before the one with the offset are copied as they are, and the messages
+    /// of that batch before the offset are written as a new batch, so the copy could replace
+    /// the segment without losing them even if the truncation is interrupted.
+    pub fn write_truncated_copy(&self, offset: usize, path: &str) -> Result<bool, Error> {
+        let position = self.start_position(offset)?;
+        if position >= self.log_size {
+            return Ok(false);
+        }
+        let (header, stored) = self.read_stored_batch(position)?;
+        if header.base_offset >= offset {
+            return Ok(false);
         }
 
-        Ok(offset_size)
+        let compression = self.codec(&header, position)?;
+        let records = compression.decompress(&stored)?;
+        let mut kept = format::decode_records(&header, &records)?;
+        kept.retain(|m| m.offset < offset);
+
+        let mut copy = Self::open(self.vfs.clone(), path.to_string(), self.base_offset)?;
+        let mut buffer = vec![0u8; COPY_BUFFER_SIZE];
+        let mut copied = FILE_HEADER_SIZE;
+        while copied < position {
+            let len = cmp::min(COPY_BUFFER_SIZE, position - copied);
+            self.log.read_exact_at(&mut buffer[..len], copied as u64)?;
+            copy.log.write_all_at(&buffer[..len], copied as u64)?;
+            copied += len;
+        }
+        copy.log_size = position;
+        copy.recover_log()?;
+
+        copy.write_batch(
+            &mut kept.into_iter().peekable(),
+            &SegmentLimits::unlimited(),
+            compression,
+        )?;
+        copy.sync()?;
+
+        Ok(true)
+    }
+
+    /// Returns true if any of the regions of this segment's log still exists.
+    pub fn has_regions(&self) -> bool {
+        Arc::strong_count(&self.log) > 1
+    }
+
+    /// Syncs the log and both indexes to the disk.
+    pub fn sync(&self) -> Result<(), Error> {
+        self.log.sync_data()?;
+        self.offset_index.sync()?;
+        self.time_index.sync()
     }
 
+    /// Returns the offset that will be given to the next message written to this segment.
+    pub fn next_offset(&self) -> usize {
+        self.next_offset
+    }
+
+    /// Returns the amount of offsets covered by this segment, which is the amount of messages
+    /// stored in it unless the segment was compacted.
+    pub fn size(&self) -> Result<usize, Error> {
+        Ok(self.next_offset - self.base_offset)
+    }
+
+    /// Returns the greatest timestamp of the messages in this segment.
+    pub fn latest_timestamp(&self) -> Option<DateTime<Utc>> {
+        self.time_index.latest_timestamp()
+    }
+
+    pub fn base_offset(&self) -> usize {
+        self.base_offset
+    }
+
+    /// Returns the size of the log in bytes.
+    pub fn size_in_bytes(&self) -> usize {
+        self.log_size
+    }
+
+    /// Returns true if the message with the given offset is stored in this segment.
     pub fn belongs_to_segment(&self, offset: usize) -> bool {
-        offset >= self.range.0 && offset < self.range.1
+        offset >= self.base_offset && offset < self.next_offset
     }
 
-    fn offset_range_guard(&self, offset: usize) -> Result<(), Error> {
-        if offset >= self.range.0 && offset < self.range.1 {
+    // offset_guard checks that the message with the given offset could be appended
+    // to the segment that ends before next_offset.
+    fn offset_guard(&self, offset: usize, next_offset: usize) -> Result<(), Error> {
+        if offset >= next_offset {
             return Ok(());
         }
 
         Err(Error::new(
             ErrorKind::InvalidInput,
             format!(
-                "given offset({}) does not belong to this segment{:?}",
-                offset, self.range
+                "given offset({}) is before the end({}) of {}",
+                offset, next_offset, self
             ),
         ))
     }
 
-    fn read_header(&self, buffer: Option<&mut [u8]>) -> Result<Header, Error> {
-        let mut owned_buffer;
-        let buffer = match buffer {
-            Some(b) => b,
-            None => {
-                owned_buffer = vec![0u8; Header::size()];
-                owned_buffer.as_mut_slice()
+    /// Returns the messages of the batch stored at the given physical position along with
+    /// the position of the next batch, or None if the position is at the end of the log.
+    pub fn read_batch(&self, position: usize) -> Result<Option<(Vec<Message>, usize)>, Error> {
+        if position >= self.log_size {
+            return Ok(None);
+        }
+
+        let (header, stored) = self.read_stored_batch(position)?;
+        let records = self.codec(&header, position)?.decompress(&stored)?;
+        let messages = format::decode_records(&header, &records)?;
+        Ok(Some((messages, position + header.batch_size())))
+    }
+
+    /// Reads the messages starting from the given offset into the range read,
+    /// until it is full or the end of the segment is reached.
+    pub fn read_range(&self, start: usize, read: &mut RangeRead) -> Result<(), Error> {
+        let mut position = self.start_position(start)?;
+        while !read.is_full() {
+            let Some((messages, next_position)) = self.read_batch(position)? else {
+                break;
+            };
+            if !read.take_batch(next_position - position) {
+                break;
+            }
+
+            for message in messages.into_iter().filter(|m| m.offset >= start) {
+                if !read.take_message(message) {
+                    break;
+                }
+            }
+            position = next_position;
+        }
+
+        Ok(())
+    }
+
+    /// Same as Segment::read_range, but returns the region of the log with the batches
+    /// instead of decoding them, or None if nothing is taken.
+    ///
+    /// The batches are taken whole and counted by their headers, so the first one could hold
+    /// the messages before the start offset, which should be skipped by the reader,
+    /// and the region could hold more messages than the limit.
+    pub fn read_range_raw(
+        &self,
+        start: usize,
+        read: &mut RangeRead,
+    ) -> Result<Option<FileRegion>, Error> {
+        let mut position = self.start_position(start)?;
+
+        // the batches appended after this point are not included in the region.
+        let log_size = self.log_size;
+        let first_position = position;
+        let first_record = read.records;
+        while position < log_size && !read.is_full() {
+            let header = self.read_batch_header(position)?;
+            if !read.take_batch(header.batch_size()) {
+                break;
+            }
+            read.records += header.records;
+            position += header.batch_size();
+        }
+
+        if position == first_position {
+            return Ok(None);
+        }
+
+        Ok(Some(FileRegion::new(
+            self.log.clone(),
+            first_position,
+            position - first_position,
+            read.records - first_record,
+        )))
+    }
+
+    /// Returns the physical position of the batch, which holds the message with the given offset
+    /// or the first message after it, or the end of the log if there is no such batch.
+    ///
+    /// The log is scanned by the batch headers from the closest offset index entry.
+    pub fn start_position(&self, offset: usize) -> Result<usize, Error> {
+        let mut position = self.offset_index.lookup(offset);
+        while position < self.log_size {
+            let header = self.read_batch_header(position)?;
+            if header.last_offset() >= offset {
+                break;
             }
+            position += header.batch_size();
+        }
+
+        Ok(position)
+    }
+
+    /// Calls f for every message in this segment in the order of offsets.
+    pub fn for_each_message<F>(&self, mut f: F) -> Result<(), Error>
+    where
+        F: FnMut(Message) -> Result<(), Error>,
+    {
+        let mut position = FILE_HEADER_SIZE;
+        while let Some((messages, next_position)) = self.read_batch(position)? {
+            position = next_position;
+            for message in messages {
+                f(message)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // load_tail restores the in-memory state of the segment by scanning the batches written after
+    // the last offset index entry. The indexes, which point outside of the log, are reported
+    // with StorageError::StaleIndexFile, so they could be rebuilt by Segment::recover.
+    fn load_tail(&mut self) -> Result<(), Error> {
+        if !self.offset_index.fits_log(self.log_size) {
+            return Err(self.stale_index(OFFSET_INDEX_EXTENSION));
+        }
+
+        let mut position = match self.offset_index.last() {
+            Some((_, physical)) => physical,
+            None => FILE_HEADER_SIZE,
         };
+        self.bytes_since_last_index = self.log_size - position;
+
+        while position < self.log_size {
+            let (header, _) = self.read_stored_batch(position)?;
+            self.next_offset = header.last_offset() + 1;
+            position += header.batch_size();
+        }
 
-        self.log.borrow_mut().read_exact(buffer)?;
+        if !self.time_index.fits_log(self.base_offset..self.next_offset) {
+            return Err(self.stale_index(TIME_INDEX_EXTENSION));
+        }
 
-        Header::deserialize(buffer)
+        self.first_timestamp = self.read_first_timestamp()?;
+        Ok(())
     }
 
-    fn open_log_file(path: String) -> Result<File, Error> {
-        OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(path)
+    fn stale_index(&self, extension: &str) -> Error {
+        StorageError::StaleIndexFile {
+            path: Self::file_path(&self.base_path, self.base_offset, extension),
+        }
+        .into()
     }
 
-    fn deserialize_message(buffer: &[u8]) -> Result<Message, Error> {
-        bincode::deserialize(&buffer).map_err(|e| {
-            Error::new(
-                ErrorKind::InvalidData,
-                format!("failed to deserialize a Message: {}", e),
-            )
+    // recover_log scans the whole log, truncates it after the last valid batch and rebuilds
+    // the in-memory state and the indexes from the batches that are left.
+    // Only the headers and the checksums of the batches are checked, the records are not decoded.
+    fn recover_log(&mut self) -> Result<RecoveryReport, Error> {
+        let mut offset_entries = Vec::new();
+        let mut time_entries = Vec::new();
+        let mut bytes_since_last_index = 0;
+        let mut next_offset = self.base_offset;
+        let mut position = FILE_HEADER_SIZE;
+
+        while position < self.log_size {
+            let header = match self.read_stored_batch(position) {
+                Ok((header, _)) => header,
+                Err(e)
+                    if matches!(
+                        StorageError::of(&e),
+                        Some(StorageError::CorruptRecord { .. })
+                    ) =>
+                {
+                    match self.is_followed_by_batch(position, next_offset) {
+                        true => return Err(e),
+                        false => break,
+                    }
+                }
+                Err(e) if Self::is_torn_batch(&e) => break,
+                Err(e) => return Err(e),
+            };
+            // offsets could have gaps after compaction, but an intact batch with an offset
+            // that goes backwards is not a result of a torn write, so nothing is truncated
+            // and the segment is left for an investigation.
+            if header.base_offset < next_offset {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "batch at {} has offset {} that is before {} in {}",
+                        position, header.base_offset, next_offset, self
+                    ),
+                ));
+            }
+
+            if bytes_since_last_index >= INDEX_INTERVAL_BYTES {
+                offset_entries.push((header.base_offset, position));
+                bytes_since_last_index = 0;
+            }
+            time_entries.push((
+                Utc.timestamp_nanos(header.max_timestamp),
+                header.base_offset,
+            ));
+
+            bytes_since_last_index += header.batch_size();
+            next_offset = header.last_offset() + 1;
+            position += header.batch_size();
+        }
+
+        let truncated_bytes = self.log_size - position;
+        if truncated_bytes > 0 {
+            self.log.set_len(position as u64)?;
+        }
+
+        self.log_size = position;
+        self.next_offset = next_offset;
+        self.bytes_since_last_index = bytes_since_last_index;
+        self.first_timestamp = self.read_first_timestamp()?;
+
+        Ok(RecoveryReport {
+            segment: self.to_string(),
+            records: next_offset - self.base_offset,
+            truncated_bytes,
+            offset_index_rebuilt: self.offset_index.rebuild(&offset_entries)?,
+            time_index_rebuilt: self.time_index.rebuild(&time_entries)?,
         })
     }
 
-    fn serialize_message(msg: Message) -> Result<Vec<u8>, Error> {
-        bincode::serialize(&msg).map_err(|e| {
-            Error::new(
-                ErrorKind::InvalidData,
-                format!("failed to serialize a Message: {}", e),
-            )
-        })
+    // is_followed_by_batch tells whether the batch at the given position is followed by
+    // an intact batch, which starts at or after the given offset.
+    fn is_followed_by_batch(&self, position: usize, next_offset: usize) -> bool {
+        let Ok(header) = self.read_batch_header(position) else {
+            return false;
+        };
+
+        match self.read_stored_batch(position + header.batch_size()) {
+            Ok((next, _)) => next.base_offset >= next_offset,
+            Err(_) => false,
+        }
     }
-}
 
-impl fmt::Display for Segment {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "[Segment #{} from `{}` with range {:?}]",
-            self.number, self.base_path, self.range
-        )
+    // is_torn_batch tells whether the error returned by read_stored_batch means that the batch
+    // is incomplete or damaged, rather than that the log could not be read at all.
+    fn is_torn_batch(e: &Error) -> bool {
+        matches!(e.kind(), ErrorKind::UnexpectedEof | ErrorKind::InvalidData)
     }
-}
 
-// Header is written before an actual message and stores the size of that message,
-// this done to simplify the read.
-#[derive(Deserialize, Serialize, Default)]
-struct Header {
-    size: usize,
-}
+    // read_first_timestamp returns the base timestamp of the first batch in the log.
+    fn read_first_timestamp(&self) -> Result<Option<DateTime<Utc>>, Error> {
+        if self.log_size <= FILE_HEADER_SIZE {
+            return Ok(None);
+        }
+
+        let header = self.read_batch_header(FILE_HEADER_SIZE)?;
+        Ok(Some(Utc.timestamp_nanos(header.base_timestamp)))
+    }
+
+    // read_stored_batch reads the batch stored at the given physical position and returns
+    // its header along with the records as they are stored, after checking their checksum.
+    fn read_stored_batch(&self, position: usize) -> Result<(BatchHeader, Vec<u8>), Error> {
+        let mut buffer = [0u8; BATCH_HEADER_SIZE];
+        let header = self.read_header(position, &mut buffer)?;
+
+        let mut stored = vec![0u8; header.size];
+        self.log
+            .read_exact_at(&mut stored, (position + BATCH_HEADER_SIZE) as u64)?;
+
+        if BatchHeader::checksum(&buffer, &stored) != header.crc {
+            return Err(StorageError::CorruptRecord {
+                segment: self.to_string(),
+                offset: header.base_offset,
+                position,
+            }
+            .into());
+        }
+
+        Ok((header, stored))
+    }
+
+    // codec returns the codec, with which the records of the batch at the given position
+    // are stored, according to the attributes of the batch.
+    fn codec(&self, header: &BatchHeader, position: usize) -> Result<Compression, Error> {
+        if header.attributes & !BatchHeader::CODEC_MASK != 0 {
+            return Err(StorageError::UnsupportedAttributes {
+                segment: self.to_string(),
+                attributes: header.attributes,
+                position,
+            }
+            .into());
+        }
 
-impl Header {
-    pub fn size() -> usize {
-        let object = Self::default();
-        bincode::serialize(&object).unwrap().len()
+        let codec = header.attributes & BatchHeader::CODEC_MASK;
+        Compression::from_id(codec).ok_or_else(|| {
+            StorageError::UnsupportedCompression {
+                segment: self.to_string(),
+                codec,
+                position,
+            }
+            .into()
+        })
     }
 
-    pub fn deserialize(buffer: &[u8]) -> Result<Self, Error> {
-        bincode::deserialize(buffer).map_err(Self::error)
+    // read_batch_header reads the header of the batch stored at the given position
+    // without reading the records.
+    fn read_batch_header(&self, position: usize) -> Result<BatchHeader, Error> {
+        self.read_header(position, &mut [0u8; BATCH_HEADER_SIZE])
     }
 
-    pub fn serialize(object: Self) -> Result<Vec<u8>, Error> {
-        bincode::serialize(&object).map_err(Self::error)
+    // read_header reads the header of the batch at the given position into the buffer
+    // and checks that the whole batch is in the log.
+    fn read_header(
+        &self,
+        position: usize,
+        buffer: &mut [u8; BATCH_HEADER_SIZE],
+    ) -> Result<BatchHeader, Error> {
+        self.log.read_exact_at(buffer, position as u64)?;
+
+        let header = BatchHeader::decode(buffer)?;
+        if header.size > self.log_size - position - BATCH_HEADER_SIZE {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("batch at {} is cut off in {}", position, self),
+            ));
+        }
+
+        Ok(header)
     }
+}
 
-    fn error(e: bincode::Error) -> Error {
-        Error::new(ErrorKind::InvalidData, e)
+impl fmt::Display for Segment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[Segment {} from `{}` with range ({}, {})]",
+            self.base_offset, self.base_path, self.base_offset, self.next_offset
+        )
     }
 }
@@ -0,0 +1,440 @@
+use core::fmt;
+use std::{
+    collections::{BTreeSet, HashMap},
+    io::{Error, ErrorKind},
+    sync::{Arc, Mutex},
+};
+
+use super::vfs::{Vfs, VfsFile};
+
+/// ENOSPC is the code of the error returned when there is no space left on the device.
+const ENOSPC: i32 = 28;
+/// EIO is the code of the error returned on a failure of the device.
+const EIO: i32 = 5;
+
+/// Fault is an error, which SimulatedVfs returns until it is cleared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// Every write that grows a file and the creation of files fail with ENOSPC.
+    NoSpace,
+    /// Every operation on the files and the directories fails with EIO.
+    Io,
+}
+
+/// SimulatedVfs keeps the files in the memory and simulates what is left of them
+/// after the machine crashes.
+///
+/// Every file has its written content and the content that was there when it was last synced,
+/// the writes that are not synced are dropped by SimulatedVfs::crash. The same goes for
+/// the entries of the directories: the files created, renamed or removed after the last sync
+/// of their directory are back to where they were after a crash. The directories themselves
+/// are created and removed right away, along with the files in the removed ones.
+/// The faults are injected on demand: a Fault makes the operations fail,
+/// and SimulatedVfs::tear_write_at cuts a write at the chosen byte.
+///
+/// The clones share the same files, so one of them could be given to a partition
+/// and the other one used to crash it.
+#[derive(Clone, Default)]
+pub struct SimulatedVfs {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    files: HashMap<String, Arc<Mutex<Node>>>,
+    /// durable_files are the files that were in their directories when they were last synced.
+    durable_files: HashMap<String, Arc<Mutex<Node>>>,
+    dirs: BTreeSet<String>,
+    fault: Option<Fault>,
+    /// file_fault is the fault of the files, which were opened by the paths ending
+    /// with the given suffix.
+    file_fault: Option<(String, Fault)>,
+    /// tear_at is the amount of bytes written before the write that is torn.
+    tear_at: Option<usize>,
+    /// halted tells that a write was torn and nothing could be changed until the crash.
+    halted: bool,
+}
+
+/// Node is the content of a file.
+#[derive(Default)]
+struct Node {
+    data: Vec<u8>,
+    /// synced is the content of the file that survives a crash.
+    synced: Vec<u8>,
+}
+
+impl SimulatedVfs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Makes the operations fail with the given fault, or stops that if it is None.
+    pub fn fail(&self, fault: Option<Fault>) {
+        self.state.lock().unwrap().fault = fault;
+    }
+
+    /// Same as SimulatedVfs::fail, but only for the operations on the files, which were opened
+    /// by the paths ending with the given suffix.
+    pub fn fail_files(&self, suffix: &str, fault: Option<Fault>) {
+        self.state.lock().unwrap().file_fault = fault.map(|fault| (suffix.to_string(), fault));
+    }
+
+    /// Tears the write, which crosses the given amount of bytes written from now on.
+    ///
+    /// The bytes of the write before that point reach the disk, while the rest of its file
+    /// is left as it was last synced, and the write fails with EIO. The machine is considered to be going down after that,
+    /// so everything that changes the files fails with EIO until SimulatedVfs::crash.
+    pub fn tear_write_at(&self, bytes: usize) {
+        self.state.lock().unwrap().tear_at = Some(bytes);
+    }
+
+    /// Returns true if a write was torn since the last crash.
+    pub fn is_halted(&self) -> bool {
+        self.state.lock().unwrap().halted
+    }
+
+    /// Drops everything that was written to the files and the directories after they were
+    /// last synced, and clears the faults.
+    ///
+    /// The files that are still open keep working on the content that is left,
+    /// but they should not be used, the same as the handles of a crashed process.
+    pub fn crash(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.files = state.durable_files.clone();
+        for node in state.files.values() {
+            let mut node = node.lock().unwrap();
+            node.data = node.synced.clone();
+        }
+
+        state.fault = None;
+        state.file_fault = None;
+        state.tear_at = None;
+        state.halted = false;
+    }
+}
+
+impl State {
+    // check returns the error, with which an operation fails. The changes fail after a torn
+    // write, and NoSpace fails only the ones that take space.
+    fn check(&self, changes: bool, takes_space: bool) -> Result<(), Error> {
+        if changes && self.halted {
+            return Err(Error::from_raw_os_error(EIO));
+        }
+
+        fault_guard(self.fault, takes_space)
+    }
+
+    // check_file is the same as State::check, but for an operation on the file
+    // opened by the given path.
+    fn check_file(&self, path: &str, changes: bool, takes_space: bool) -> Result<(), Error> {
+        self.check(changes, takes_space)?;
+
+        match &self.file_fault {
+            Some((suffix, fault)) if path.ends_with(suffix.as_str()) => {
+                fault_guard(Some(*fault), takes_space)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn dir_guard(&self, path: &str) -> Result<(), Error> {
+        match is_root(path) || self.dirs.contains(path) {
+            true => Ok(()),
+            false => Err(not_found(path)),
+        }
+    }
+}
+
+impl Vfs for SimulatedVfs {
+    fn open(&self, path: &str) -> Result<Arc<dyn VfsFile>, Error> {
+        let path = normalize(path);
+        let mut state = self.state.lock().unwrap();
+
+        let node = match state.files.get(&path) {
+            Some(node) => {
+                state.check(false, false)?;
+                node.clone()
+            }
+            None => {
+                state.check(true, true)?;
+                state.dir_guard(parent(&path))?;
+
+                let node = Arc::new(Mutex::new(Node::default()));
+                state.files.insert(path.clone(), node.clone());
+                node
+            }
+        };
+
+        Ok(Arc::new(SimulatedFile {
+            state: self.state.clone(),
+            node,
+            path,
+        }))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        let path = normalize(path);
+        let state = self.state.lock().unwrap();
+
+        state.files.contains_key(&path) || state.dirs.contains(&path)
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<String>, Error> {
+        let path = normalize(path);
+        let state = self.state.lock().unwrap();
+        state.check(false, false)?;
+        state.dir_guard(&path)?;
+
+        Ok(state
+            .files
+            .keys()
+            .filter(|file| parent(file) == path)
+            .map(|file| file[file.rfind('/').map_or(0, |i| i + 1)..].to_string())
+            .collect())
+    }
+
+    fn create_dir_all(&self, path: &str) -> Result<(), Error> {
+        let path = normalize(path);
+        let mut state = self.state.lock().unwrap();
+        state.check(true, true)?;
+
+        let mut dir = path.as_str();
+        while !is_root(dir) {
+            if state.files.contains_key(dir) {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("`{}` is a file", dir),
+                ));
+            }
+            state.dirs.insert(dir.to_string());
+            dir = parent(dir);
+        }
+
+        Ok(())
+    }
+
+    fn remove_dir_all(&self, path: &str) -> Result<(), Error> {
+        let path = normalize(path);
+        let mut state = self.state.lock().unwrap();
+        state.check(true, false)?;
+        if !state.dirs.contains(&path) {
+            return Err(not_found(&path));
+        }
+
+        let prefix = format!("{}/", path);
+        state.files.retain(|file, _| !file.starts_with(&prefix));
+        state
+            .durable_files
+            .retain(|file, _| !file.starts_with(&prefix));
+        state
+            .dirs
+            .retain(|dir| *dir != path && !dir.starts_with(&prefix));
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &str) -> Result<(), Error> {
+        let path = normalize(path);
+        let mut state = self.state.lock().unwrap();
+        state.check(true, false)?;
+
+        match state.files.remove(&path) {
+            Some(_) => Ok(()),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), Error> {
+        let (from, to) = (normalize(from), normalize(to));
+        let mut state = self.state.lock().unwrap();
+        state.check(true, false)?;
+        state.dir_guard(parent(&to))?;
+
+        let Some(node) = state.files.remove(&from) else {
+            return Err(not_found(&from));
+        };
+        state.files.insert(to, node);
+        Ok(())
+    }
+
+    fn sync_dir(&self, path: &str) -> Result<(), Error> {
+        let path = normalize(path);
+        let mut state = self.state.lock().unwrap();
+        state.check(true, false)?;
+        state.dir_guard(&path)?;
+
+        let State {
+            files,
+            durable_files,
+            ..
+        } = &mut *state;
+        durable_files.retain(|file, _| parent(file) != path);
+        durable_files.extend(
+            files
+                .iter()
+                .filter(|(file, _)| parent(file) == path)
+                .map(|(file, node)| (file.clone(), node.clone())),
+        );
+        Ok(())
+    }
+}
+
+impl fmt::Debug for SimulatedVfs {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = self.state.lock().unwrap();
+        f.debug_struct("SimulatedVfs")
+            .field("files", &state.files.len())
+            .field("dirs", &state.dirs.len())
+            .field("fault", &state.fault)
+            .field("file_fault", &state.file_fault)
+            .field("halted", &state.halted)
+            .finish()
+    }
+}
+
+/// SimulatedFile is a file opened by SimulatedVfs, it keeps working after the file is
+/// renamed or removed.
+struct SimulatedFile {
+    state: Arc<Mutex<State>>,
+    node: Arc<Mutex<Node>>,
+    /// path is the path, by which the file was opened.
+    path: String,
+}
+
+impl VfsFile for SimulatedFile {
+    fn read_exact_at(&self, buffer: &mut [u8], position: u64) -> Result<(), Error> {
+        self.state
+            .lock()
+            .unwrap()
+            .check_file(&self.path, false, false)?;
+        let node = self.node.lock().unwrap();
+
+        let start = position as usize;
+        let Some(data) = node.data.get(start..start + buffer.len()) else {
+            return Err(Error::new(
+                ErrorKind::UnexpectedEof,
+                format!("failed to read {} bytes at {}", buffer.len(), position),
+            ));
+        };
+        buffer.copy_from_slice(data);
+        Ok(())
+    }
+
+    fn write_all_at(&self, data: &[u8], position: u64) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        let mut node = self.node.lock().unwrap();
+
+        let start = position as usize;
+        state.check_file(&self.path, true, start + data.len() > node.data.len())?;
+
+        let (data, torn) = match state.tear_at {
+            Some(left) if data.len() > left => (&data[..left], true),
+            Some(left) => {
+                state.tear_at = Some(left - data.len());
+                (data, false)
+            }
+            None => (data, false),
+        };
+
+        if node.data.len() < start + data.len() {
+            node.data.resize(start + data.len(), 0);
+        }
+        node.data[start..start + data.len()].copy_from_slice(data);
+
+        if torn {
+            let synced = &mut node.synced;
+            if synced.len() < start + data.len() {
+                synced.resize(start + data.len(), 0);
+            }
+            synced[start..start + data.len()].copy_from_slice(data);
+            state.tear_at = None;
+            state.halted = true;
+            return Err(Error::from_raw_os_error(EIO));
+        }
+        Ok(())
+    }
+
+    fn size(&self) -> Result<u64, Error> {
+        self.state
+            .lock()
+            .unwrap()
+            .check_file(&self.path, false, false)?;
+        Ok(self.node.lock().unwrap().data.len() as u64)
+    }
+
+    fn set_len(&self, len: u64) -> Result<(), Error> {
+        let state = self.state.lock().unwrap();
+        let mut node = self.node.lock().unwrap();
+        state.check_file(&self.path, true, len as usize > node.data.len())?;
+
+        node.data.resize(len as usize, 0);
+        Ok(())
+    }
+
+    fn sync_data(&self) -> Result<(), Error> {
+        self.state
+            .lock()
+            .unwrap()
+            .check_file(&self.path, true, false)?;
+        let mut node = self.node.lock().unwrap();
+
+        node.synced = node.data.clone();
+        Ok(())
+    }
+
+    fn as_file(&self) -> Option<&std::fs::File> {
+        None
+    }
+}
+
+impl fmt::Debug for SimulatedFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SimulatedFile")
+            .field("path", &self.path)
+            .field("size", &self.node.lock().unwrap().data.len())
+            .finish()
+    }
+}
+
+// normalize returns the path without the empty and `.` parts, so that `a//b/./c/`
+// and `a/b/c` are the same file.
+fn normalize(path: &str) -> String {
+    let parts: Vec<&str> = path
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .collect();
+
+    match path.starts_with('/') {
+        true => format!("/{}", parts.join("/")),
+        false => parts.join("/"),
+    }
+}
+
+// parent returns the directory of the normalized path, which is empty for the current one.
+fn parent(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) if path.len() > 1 => "/",
+        Some(0) => "",
+        Some(i) => &path[..i],
+        None => "",
+    }
+}
+
+// is_root tells whether the normalized path is the root or the current directory,
+// which always exist.
+fn is_root(path: &str) -> bool {
+    path.is_empty() || path == "/"
+}
+
+// fault_guard returns the error of the fault, NoSpace fails only the operations
+// that take space.
+fn fault_guard(fault: Option<Fault>, takes_space: bool) -> Result<(), Error> {
+    match fault {
+        Some(Fault::Io) => Err(Error::from_raw_os_error(EIO)),
+        Some(Fault::NoSpace) if takes_space => Err(Error::from_raw_os_error(ENOSPC)),
+        _ => Ok(()),
+    }
+}
+
+fn not_found(path: &str) -> Error {
+    Error::new(ErrorKind::NotFound, format!("`{}` is not found", path))
+}
@@ -0,0 +1,482 @@
+use std::{
+    collections::{BTreeMap, VecDeque},
+    io::{Error, ErrorKind},
+    ops::Bound,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, RwLock,
+    },
+};
+
+use chrono::{DateTime, Utc};
+
+use super::{
+    format::SegmentManifest,
+    region::FileRegion,
+    remote::RemoteStorage,
+    segment::{RangeRead, Segment, LOG_EXTENSION, OFFSET_INDEX_EXTENSION, TIME_INDEX_EXTENSION},
+    vfs::{Vfs, VfsFile},
+};
+
+/// MANIFEST_EXTENSION is the extension of the object, which describes a segment
+/// in the remote storage. It is uploaded after the segment's files and deleted before them,
+/// so only the segments with a manifest are complete.
+const MANIFEST_EXTENSION: &str = "manifest";
+
+/// CACHE_DIR is the directory in a partition, into which the segments are fetched
+/// from the remote storage.
+const CACHE_DIR: &str = "remote-cache";
+
+/// FETCH_DIR_PREFIX is the prefix of the directories inside of the cache, into which
+/// the segments are downloaded before they are moved to the cache.
+const FETCH_DIR_PREFIX: &str = "fetch-";
+
+/// RemoteTier keeps track of the segments of a partition that are copied to the remote storage
+/// and fetches them into a local cache, when the offsets that are not on the local disk anymore
+/// are read.
+///
+/// The objects of a segment are stored under the keys `{partition number}/{file name}`,
+/// so a remote storage could be shared by the partitions stored in the same directory.
+/// The reads take the end of the remote tier, which is the base offset of the first local
+/// segment, and read only the segments before it, since the rest are read from the disk.
+/// The segments are downloaded without locking the cache, so a download does not hold up
+/// the reads of the segments, which are already fetched.
+pub struct RemoteTier {
+    storage: Arc<dyn RemoteStorage>,
+    /// vfs is the file system of the partition, which stores the cache as well.
+    vfs: Arc<dyn Vfs>,
+    /// prefix is the part of the keys shared by the objects of this partition.
+    prefix: String,
+    /// cache_path is the directory, into which the segments are fetched.
+    cache_path: String,
+    /// cache_segments is the max amount of the fetched segments kept in the cache.
+    cache_segments: usize,
+    /// segments are the manifests of the segments in the remote storage by their base offsets.
+    segments: RwLock<BTreeMap<usize, SegmentManifest>>,
+    /// cached are the fetched segments from the least to the most recently used one.
+    cached: Mutex<VecDeque<Arc<RwLock<Segment>>>>,
+    /// fetches is the amount of started downloads, which names their directories.
+    fetches: AtomicUsize,
+}
+
+impl RemoteTier {
+    /// Opens the remote tier of the partition stored in the given directory and reads
+    /// the manifests of its segments. The cache is cleared, since a fetch could have been
+    /// interrupted by a crash.
+    pub fn open(
+        storage: Arc<dyn RemoteStorage>,
+        vfs: Arc<dyn Vfs>,
+        path: &str,
+        number: usize,
+        cache_segments: usize,
+    ) -> Result<Self, Error> {
+        let cache_path = format!("{}/{}", path, CACHE_DIR);
+        remove_dir(vfs.as_ref(), &cache_path)?;
+        vfs.create_dir_all(&cache_path)?;
+
+        let prefix = format!("{:08}/", number);
+        let manifest_path = format!("{}/{}", cache_path, MANIFEST_EXTENSION);
+
+        let mut segments = BTreeMap::new();
+        for key in storage.list(&prefix)? {
+            if !key.ends_with(MANIFEST_EXTENSION) {
+                continue;
+            }
+
+            let file = vfs.open(&manifest_path)?;
+            storage.download(&key, file.as_ref())?;
+            let manifest = SegmentManifest::decode(&read_file(file.as_ref())?, &key)?;
+            segments.insert(manifest.base_offset, manifest);
+        }
+        match vfs.remove_file(&manifest_path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => return Err(e),
+            _ => {}
+        }
+
+        Ok(Self {
+            storage,
+            vfs,
+            prefix,
+            cache_path,
+            cache_segments: cache_segments.max(1),
+            segments: RwLock::new(segments),
+            cached: Mutex::new(VecDeque::new()),
+            fetches: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the base offset of the first segment in the remote storage.
+    pub fn first_offset(&self) -> Option<usize> {
+        self.segments.read().unwrap().keys().next().copied()
+    }
+
+    /// Returns the offset after the last message in the remote storage.
+    pub fn end_offset(&self) -> Option<usize> {
+        let segments = self.segments.read().unwrap();
+        segments.values().next_back().map(|m| m.next_offset)
+    }
+
+    pub fn contains(&self, base_offset: usize) -> bool {
+        self.segments.read().unwrap().contains_key(&base_offset)
+    }
+
+    /// Returns the manifests of the segments in the remote storage in the order of their offsets.
+    pub fn manifests(&self) -> Vec<SegmentManifest> {
+        self.segments.read().unwrap().values().cloned().collect()
+    }
+
+    /// Copies the files of the closed segment from the partition in the given directory
+    /// to the remote storage and returns its manifest.
+    ///
+    /// The segment is not a part of the remote tier until RemoteTier::add is called with the
+    /// manifest, before which the uploaded files could be removed by RemoteTier::delete.
+    ///
+    /// The files are not created if they are missing, so the indexes, which were removed after
+    /// a failed write, have to be restored by Segment::restore_indexes before the upload.
+    pub fn upload(&self, path: &str, segment: &Segment) -> Result<SegmentManifest, Error> {
+        let base_offset = segment.base_offset();
+        for extension in [OFFSET_INDEX_EXTENSION, TIME_INDEX_EXTENSION, LOG_EXTENSION] {
+            let file_path = Segment::file_path(path, base_offset, extension);
+            if !self.vfs.exists(&file_path) {
+                return Err(Error::new(
+                    ErrorKind::NotFound,
+                    format!("file `{}` of {} is not found", file_path, segment),
+                ));
+            }
+            let file = self.vfs.open(&file_path)?;
+            self.storage
+                .upload(file.as_ref(), &self.key(base_offset, extension))?;
+        }
+
+        Ok(SegmentManifest {
+            base_offset,
+            next_offset: segment.next_offset(),
+            latest_timestamp: segment.latest_timestamp(),
+            size_in_bytes: segment.size_in_bytes(),
+        })
+    }
+
+    /// Completes the upload of the segment by uploading its manifest.
+    pub fn add(&self, manifest: SegmentManifest) -> Result<(), Error> {
+        let base_offset = manifest.base_offset;
+        let manifest_path = Segment::file_path(&self.cache_path, base_offset, MANIFEST_EXTENSION);
+
+        let file = self.vfs.open(&manifest_path)?;
+        let uploaded = file.write_all_at(&manifest.encode(), 0).and_then(|_| {
+            self.storage
+                .upload(file.as_ref(), &self.key(base_offset, MANIFEST_EXTENSION))
+        });
+        self.vfs.remove_file(&manifest_path)?;
+        uploaded?;
+
+        self.segments.write().unwrap().insert(base_offset, manifest);
+        Ok(())
+    }
+
+    /// Deletes the segment with the given base offset from the remote storage and the cache.
+    pub fn delete(&self, base_offset: usize) -> Result<(), Error> {
+        let keys = self.remove(base_offset)?;
+        self.delete_objects(&keys)
+    }
+
+    /// Removes the segment with the given base offset from the remote tier and the cache,
+    /// and returns the keys of its objects, which have to be deleted by RemoteTier::delete_objects.
+    ///
+    /// Only the local state is changed, so it could be done while the partition is locked,
+    /// and the objects are deleted from the storage after that.
+    pub fn remove(&self, base_offset: usize) -> Result<Vec<String>, Error> {
+        self.segments.write().unwrap().remove(&base_offset);
+        self.evict(base_offset)?;
+
+        // the manifest is deleted first, so the rest of the objects are never seen without it.
+        Ok([
+            MANIFEST_EXTENSION,
+            OFFSET_INDEX_EXTENSION,
+            TIME_INDEX_EXTENSION,
+            LOG_EXTENSION,
+        ]
+        .iter()
+        .map(|extension| self.key(base_offset, extension))
+        .collect())
+    }
+
+    /// Deletes the objects with the given keys from the remote storage in the given order.
+    pub fn delete_objects(&self, keys: &[String]) -> Result<(), Error> {
+        for key in keys {
+            self.storage.delete(key)?;
+        }
+
+        Ok(())
+    }
+
+    /// Deletes the segments, which are entirely before the given offset,
+    /// and returns their base offsets.
+    pub fn delete_before(&self, offset: usize) -> Result<Vec<usize>, Error> {
+        let deleted: Vec<usize> = self
+            .manifests()
+            .iter()
+            .take_while(|m| m.next_offset <= offset)
+            .map(|m| m.base_offset)
+            .collect();
+
+        for &base_offset in deleted.iter() {
+            self.delete(base_offset)?;
+        }
+        Ok(deleted)
+    }
+
+    /// Deletes the segments, which have any messages at or after the given offset.
+    pub fn delete_from(&self, offset: usize) -> Result<(), Error> {
+        for manifest in self.manifests().iter().rev() {
+            if manifest.next_offset <= offset {
+                break;
+            }
+            self.delete(manifest.base_offset)?;
+        }
+
+        Ok(())
+    }
+
+    /// Returns the remote segment before the end, which stores the given offset,
+    /// fetching it into the cache if needed.
+    pub fn segment(
+        &self,
+        offset: usize,
+        end: usize,
+    ) -> Result<Option<Arc<RwLock<Segment>>>, Error> {
+        let base_offset = {
+            let segments = self.segments.read().unwrap();
+            match segments.range(..=offset).next_back() {
+                Some((&base_offset, m)) if offset < m.next_offset && base_offset < end => {
+                    base_offset
+                }
+                _ => return Ok(None),
+            }
+        };
+
+        self.fetch(base_offset).map(Some)
+    }
+
+    /// Returns the remote segment before the end, which follows the one with the given
+    /// base offset, fetching it into the cache if needed.
+    pub fn segment_after(
+        &self,
+        base_offset: usize,
+        end: usize,
+    ) -> Result<Option<Arc<RwLock<Segment>>>, Error> {
+        if base_offset >= end {
+            return Ok(None);
+        }
+
+        let next = {
+            let segments = self.segments.read().unwrap();
+            segments
+                .range((Bound::Excluded(base_offset), Bound::Excluded(end)))
+                .next()
+                .map(|(&base_offset, _)| base_offset)
+        };
+
+        next.map(|base_offset| self.fetch(base_offset)).transpose()
+    }
+
+    /// Same as Segment::read_range, but reads the remote segments from the given offset
+    /// until the end or until the read is full.
+    pub fn read_range(&self, start: usize, end: usize, read: &mut RangeRead) -> Result<(), Error> {
+        let mut offset = start;
+        while !read.is_full() && offset < end {
+            let Some(segment) = self.segment(offset, end)? else {
+                break;
+            };
+            let segment = segment.read().unwrap();
+            segment.read_range(start, read)?;
+            offset = segment.next_offset();
+        }
+
+        Ok(())
+    }
+
+    /// Same as Segment::read_range_raw, but reads the remote segments from the given offset
+    /// until the end or until the read is full.
+    pub fn read_range_raw(
+        &self,
+        start: usize,
+        end: usize,
+        read: &mut RangeRead,
+        regions: &mut Vec<FileRegion>,
+    ) -> Result<(), Error> {
+        let mut offset = start;
+        while !read.is_full() && offset < end {
+            let Some(segment) = self.segment(offset, end)? else {
+                break;
+            };
+            let segment = segment.read().unwrap();
+            if let Some(region) = segment.read_range_raw(start, read)? {
+                regions.push(region);
+            }
+            offset = segment.next_offset();
+        }
+
+        Ok(())
+    }
+
+    /// Returns the offset of the first message in the remote segments before the end
+    /// with the timestamp at or after the given one.
+    ///
+    /// Only the segments, which have such a message according to their manifests, are fetched.
+    pub fn offset_for_timestamp(
+        &self,
+        timestamp: DateTime<Utc>,
+        end: usize,
+    ) -> Result<Option<usize>, Error> {
+        for manifest in self.manifests() {
+            if manifest.base_offset >= end {
+                break;
+            }
+            if manifest
+                .latest_timestamp
+                .is_none_or(|latest| latest < timestamp)
+            {
+                continue;
+            }
+
+            let segment = self.fetch(manifest.base_offset)?;
+            let offset = segment.read().unwrap().offset_for_timestamp(timestamp)?;
+            if offset.is_some() {
+                return Ok(offset);
+            }
+        }
+
+        Ok(None)
+    }
+
+    // fetch returns the cached segment with the given base offset, downloading it first
+    // if it is not in the cache, and evicts the least recently used segments.
+    //
+    // The segment is downloaded into its own directory without the cache locked, then moved
+    // to the cache, unless another fetch of it has finished first or it was deleted meanwhile.
+    fn fetch(&self, base_offset: usize) -> Result<Arc<RwLock<Segment>>, Error> {
+        if let Some(segment) = self.cached(&mut self.cached.lock().unwrap(), base_offset) {
+            return Ok(segment);
+        }
+
+        let fetch_path = format!(
+            "{}/{}{}",
+            self.cache_path,
+            FETCH_DIR_PREFIX,
+            self.fetches.fetch_add(1, Ordering::AcqRel)
+        );
+        let downloaded = self.download(&fetch_path, base_offset);
+
+        let mut cached = self.cached.lock().unwrap();
+        let segment = match (downloaded, self.cached(&mut cached, base_offset)) {
+            (_, Some(segment)) => Ok(segment),
+            (Err(e), None) => Err(e),
+            (Ok(()), None) if !self.contains(base_offset) => Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "segment with offset {} is deleted from the remote storage",
+                    base_offset
+                ),
+            )),
+            (Ok(()), None) => self.add_to_cache(&mut cached, &fetch_path, base_offset),
+        };
+        remove_dir(self.vfs.as_ref(), &fetch_path)?;
+
+        segment
+    }
+
+    // cached returns the segment with the given base offset from the cache,
+    // marking it as the most recently used one.
+    fn cached(
+        &self,
+        cached: &mut VecDeque<Arc<RwLock<Segment>>>,
+        base_offset: usize,
+    ) -> Option<Arc<RwLock<Segment>>> {
+        let i = cached
+            .iter()
+            .position(|s| s.read().unwrap().base_offset() == base_offset)?;
+
+        let segment = cached.remove(i).unwrap();
+        cached.push_back(segment.clone());
+        Some(segment)
+    }
+
+    // download writes the files of the segment with the given base offset
+    // from the remote storage into a new directory.
+    fn download(&self, path: &str, base_offset: usize) -> Result<(), Error> {
+        self.vfs.create_dir_all(path)?;
+
+        for extension in [LOG_EXTENSION, OFFSET_INDEX_EXTENSION, TIME_INDEX_EXTENSION] {
+            let file = self
+                .vfs
+                .open(&Segment::file_path(path, base_offset, extension))?;
+            self.storage
+                .download(&self.key(base_offset, extension), file.as_ref())?;
+        }
+
+        Ok(())
+    }
+
+    // add_to_cache moves the downloaded segment into the cache and opens it, then evicts
+    // the least recently used segments.
+    fn add_to_cache(
+        &self,
+        cached: &mut VecDeque<Arc<RwLock<Segment>>>,
+        path: &str,
+        base_offset: usize,
+    ) -> Result<Arc<RwLock<Segment>>, Error> {
+        Segment::replace_files(self.vfs.as_ref(), path, &self.cache_path, base_offset)?;
+        let segment = Arc::new(RwLock::new(Segment::new(
+            self.vfs.clone(),
+            self.cache_path.clone(),
+            base_offset,
+        )?));
+        cached.push_back(segment.clone());
+
+        // the evicted segments could still be read by someone, which is fine,
+        // since their files stay readable until they are closed.
+        while cached.len() > self.cache_segments {
+            let evicted = cached.pop_front().unwrap();
+            evicted.read().unwrap().delete()?;
+        }
+
+        Ok(segment)
+    }
+
+    // evict removes the segment with the given base offset from the cache.
+    fn evict(&self, base_offset: usize) -> Result<(), Error> {
+        let mut cached = self.cached.lock().unwrap();
+
+        if let Some(i) = cached
+            .iter()
+            .position(|s| s.read().unwrap().base_offset() == base_offset)
+        {
+            let evicted = cached.remove(i).unwrap();
+            evicted.read().unwrap().delete()?;
+        }
+
+        Ok(())
+    }
+
+    fn key(&self, base_offset: usize, extension: &str) -> String {
+        format!(
+            "{}{}",
+            self.prefix,
+            Segment::file_name(base_offset, extension)
+        )
+    }
+}
+
+// read_file reads the whole content of the file.
+fn read_file(file: &dyn VfsFile) -> Result<Vec<u8>, Error> {
+    let mut data = vec![0u8; file.size()? as usize];
+    file.read_exact_at(&mut data, 0)?;
+    Ok(data)
+}
+
+fn remove_dir(vfs: &dyn Vfs, path: &str) -> Result<(), Error> {
+    match vfs.remove_dir_all(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
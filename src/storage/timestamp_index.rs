@@ -1,116 +1,228 @@
 use std::{
-    cell::RefCell,
-    fs::{self, File, OpenOptions},
-    io::{Error, ErrorKind, Read, Seek, SeekFrom, Write},
-    os::unix::fs::MetadataExt,
+    io::{Error, ErrorKind},
+    ops::Range,
+    sync::Arc,
 };
 
+use super::{
+    format::{self, FileKind, FILE_HEADER_SIZE, INDEX_ENTRY_SIZE},
+    vfs::{Vfs, VfsFile},
+};
 use chrono::{DateTime, TimeZone, Utc};
-use serde::{Deserialize, Serialize};
 
+/// TimestampIndex maps the max timestamps of the batches in a segment to their base offsets.
+///
+/// An entry is added only when a batch has a greater max timestamp than all of the previous ones,
+/// so the entries stay sorted by both fields even if the messages are not, and the first batch
+/// with a message at or after a given timestamp can be found with a binary search.
 pub struct TimestampIndex {
-    file: RefCell<File>,
-    path: String,
+    file: Arc<dyn VfsFile>,
+    entries: Vec<Index>,
+    /// detached tells that a write to the file failed, so the file is behind the entries
+    /// and is not written to or synced anymore.
+    detached: bool,
 }
 
 impl TimestampIndex {
-    pub fn new(path: String) -> Result<Self, Error> {
-        match OpenOptions::new()
-            .create(true)
-            .read(true)
-            .write(true)
-            .open(path.clone())
-        {
-            Ok(file) => Ok(Self {
-                file: RefCell::new(file),
-                path,
-            }),
-            Err(e) => Err(e),
+    pub fn new(vfs: &dyn Vfs, path: String) -> Result<Self, Error> {
+        let file = vfs.open(&path)?;
+        format::version_guard(&*file, FileKind::TimeIndex, &path)?;
+
+        let entries = Self::read_entries(&*file)?;
+
+        Ok(Self {
+            file,
+            entries,
+            detached: false,
+        })
+    }
+
+    /// Adds the entries for the given (max timestamp, base offset) pairs of the appended batches
+    /// to the index with a single write.
+    ///
+    /// The entries are added even if the write fails, then the index is detached from its file.
+    pub fn append(&mut self, messages: &[(DateTime<Utc>, usize)]) -> Result<(), Error> {
+        let mut entries = Vec::new();
+        let mut latest = self.entries.last().map(|e| e.timestamp);
+
+        for &(timestamp, offset) in messages {
+            let timestamp = Self::nanos(timestamp)?;
+            if latest.is_some_and(|latest| latest >= timestamp) {
+                continue;
+            }
+
+            entries.push(Index { offset, timestamp });
+            latest = Some(timestamp);
+        }
+
+        if entries.is_empty() {
+            return Ok(());
+        }
+
+        let mut data = Vec::with_capacity(entries.len() * INDEX_ENTRY_SIZE);
+        for index in entries.iter() {
+            data.extend(Index::serialize(index));
         }
+
+        // the entries are written right after the last one, over a torn entry if there is one.
+        let position = FILE_HEADER_SIZE + self.entries.len() * INDEX_ENTRY_SIZE;
+        self.entries.extend(entries);
+        self.write_entries(&data, position)
     }
 
-    pub fn write(&self, timestamp: DateTime<Utc>, offset: usize) -> Result<(), Error> {
-        let data = Index::serialize(Index {
-            offset,
-            timestamp: timestamp.timestamp_nanos_opt().unwrap(),
-        })?;
+    /// Replaces the content of the index with the entries for the given
+    /// (max timestamp, base offset) pairs of all of the batches in the segment.
+    /// Returns false and does nothing if the index already contains exactly these entries.
+    pub fn rebuild(&mut self, messages: &[(DateTime<Utc>, usize)]) -> Result<bool, Error> {
+        let mut entries: Vec<Index> = Vec::new();
+        for &(timestamp, offset) in messages {
+            let timestamp = Self::nanos(timestamp)?;
+            match entries.last() {
+                Some(last) if last.timestamp >= timestamp => continue,
+                _ => entries.push(Index { offset, timestamp }),
+            }
+        }
+        if entries == self.entries {
+            return Ok(false);
+        }
+
+        let mut data = Vec::with_capacity(entries.len() * INDEX_ENTRY_SIZE);
+        for index in entries.iter() {
+            data.extend(Index::serialize(index));
+        }
 
-        let mut file = self.file.borrow_mut();
+        format::write_file_header(&*self.file, FileKind::TimeIndex)?;
+        self.file.write_all_at(&data, FILE_HEADER_SIZE as u64)?;
+        // the stale entries after the rebuilt ones are cut off.
+        self.file.set_len((FILE_HEADER_SIZE + data.len()) as u64)?;
 
-        file.seek(SeekFrom::End(0))?;
-        file.write_all(&data)
+        self.entries = entries;
+        Ok(true)
     }
 
-    pub fn read(&self, timestamp: DateTime<Utc>) -> Result<usize, Error> {
-        let timestamp = timestamp.timestamp_nanos_opt().unwrap();
-        let mut buffer = vec![0u8; Index::size()];
+    /// Removes the entries for the batches with the base offsets at or after the given one.
+    pub fn truncate(&mut self, offset: usize) -> Result<(), Error> {
+        let keep = self.entries.partition_point(|e| e.offset < offset);
+        if keep == self.entries.len() {
+            return Ok(());
+        }
 
-        self.file.borrow_mut().seek(SeekFrom::Start(0))?;
-        loop {
-            let index = self.read_index(Some(&mut buffer))?;
-            if index.timestamp == timestamp {
-                return Ok(index.offset);
-            }
+        if !self.detached {
+            self.file
+                .set_len((FILE_HEADER_SIZE + keep * INDEX_ENTRY_SIZE) as u64)?;
         }
+        self.entries.truncate(keep);
+        Ok(())
+    }
+
+    /// Returns the base offset of the first batch with a message at or after the given timestamp,
+    /// or None if all of the messages are older.
+    pub fn lookup(&self, timestamp: DateTime<Utc>) -> Option<usize> {
+        // the timestamps, which could not be stored in the index, are either before
+        // or after all of its entries.
+        let Some(timestamp) = timestamp.timestamp_nanos_opt() else {
+            return match timestamp < DateTime::UNIX_EPOCH {
+                true => self.entries.first().map(|e| e.offset),
+                false => None,
+            };
+        };
+        let idx = self.entries.partition_point(|e| e.timestamp < timestamp);
+        self.entries.get(idx).map(|e| e.offset)
     }
 
-    pub fn latest_timestamp(&self) -> Result<DateTime<Utc>, Error> {
-        let offset = -(Index::size() as i64);
+    /// Tells whether every entry points to an offset in the given range, in the ascending order.
+    pub fn fits_log(&self, offsets: Range<usize>) -> bool {
+        self.entries.iter().all(|e| offsets.contains(&e.offset))
+            && self
+                .entries
+                .windows(2)
+                .all(|w| w[0].offset < w[1].offset && w[0].timestamp <= w[1].timestamp)
+    }
 
-        self.file.borrow_mut().seek(SeekFrom::End(offset))?;
-        let index = self.read_index(None)?;
+    // nanos returns the timestamp as it is stored in the index.
+    fn nanos(timestamp: DateTime<Utc>) -> Result<i64, Error> {
+        timestamp.timestamp_nanos_opt().ok_or_else(|| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("timestamp {} could not be indexed", timestamp),
+            )
+        })
+    }
 
-        Ok(Utc.timestamp_nanos(index.timestamp))
+    /// Returns the greatest timestamp stored in the index.
+    pub fn latest_timestamp(&self) -> Option<DateTime<Utc>> {
+        self.entries
+            .last()
+            .map(|e| Utc.timestamp_nanos(e.timestamp))
     }
 
-    pub fn size(&self) -> Result<usize, Error> {
-        let file_size = fs::metadata(self.path.clone())?.size() as usize;
-        Ok(file_size / Index::size())
+    /// Writes all of the entries to a new file at the given path and syncs it, if the index
+    /// is detached from its file, and tells whether it was.
+    pub fn reattach(&mut self, vfs: &dyn Vfs, path: &str) -> Result<bool, Error> {
+        if !self.detached {
+            return Ok(false);
+        }
+
+        let mut data = Vec::with_capacity(self.entries.len() * INDEX_ENTRY_SIZE);
+        for index in self.entries.iter() {
+            data.extend(Index::serialize(index));
+        }
+
+        let file = vfs.open(path)?;
+        format::write_file_header(&*file, FileKind::TimeIndex)?;
+        file.write_all_at(&data, FILE_HEADER_SIZE as u64)?;
+        file.set_len((FILE_HEADER_SIZE + data.len()) as u64)?;
+        file.sync_data()?;
+
+        self.file = file;
+        self.detached = false;
+        Ok(true)
     }
 
-    fn read_index(&self, buffer: Option<&mut [u8]>) -> Result<Index, Error> {
-        let mut owned_buffer;
-        let buffer = match buffer {
-            Some(b) => b,
-            None => {
-                owned_buffer = vec![0u8; Index::size()];
-                owned_buffer.as_mut_slice()
-            }
-        };
+    pub fn sync(&self) -> Result<(), Error> {
+        match self.detached {
+            true => Ok(()),
+            false => self.file.sync_data(),
+        }
+    }
 
-        let bytes_read = self.file.borrow_mut().read(buffer)?;
-        if bytes_read != Index::size() {
-            return Err(Error::new(
-                ErrorKind::NotFound,
-                "index not found in the file",
-            ));
+    // write_entries writes the serialized entries to the file at the given position,
+    // unless the index is detached, and detaches it if the write fails.
+    fn write_entries(&mut self, data: &[u8], position: usize) -> Result<(), Error> {
+        if self.detached {
+            return Ok(());
         }
 
-        Index::deserialize(buffer)
+        let result = self.file.write_all_at(data, position as u64);
+        self.detached = result.is_err();
+        result
+    }
+
+    fn read_entries(file: &dyn VfsFile) -> Result<Vec<Index>, Error> {
+        let size = file.size()? as usize;
+        let mut buffer = vec![0u8; size.saturating_sub(FILE_HEADER_SIZE)];
+        file.read_exact_at(&mut buffer, FILE_HEADER_SIZE as u64)?;
+
+        buffer
+            .chunks_exact(INDEX_ENTRY_SIZE)
+            .map(Index::deserialize)
+            .collect()
     }
 }
 
-#[derive(Serialize, Deserialize, Default)]
+#[derive(PartialEq)]
 struct Index {
     offset: usize,
     timestamp: i64,
 }
 
 impl Index {
-    pub fn size() -> usize {
-        let object = Self::default();
-        bincode::serialize(&object).unwrap().len()
-    }
-
     pub fn deserialize(buffer: &[u8]) -> Result<Self, Error> {
-        bincode::deserialize(buffer).map_err(Self::error)
-    }
-
-    pub fn serialize(object: Self) -> Result<Vec<u8>, Error> {
-        bincode::serialize(&object).map_err(Self::error)
+        let (timestamp, offset) = format::decode_time_entry(buffer)?;
+        Ok(Self { offset, timestamp })
     }
 
-    fn error(e: bincode::Error) -> Error {
-        Error::new(ErrorKind::InvalidData, e)
+    pub fn serialize(object: &Self) -> [u8; INDEX_ENTRY_SIZE] {
+        format::encode_time_entry(object.timestamp, object.offset)
     }
 }
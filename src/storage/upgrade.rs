@@ -0,0 +1,238 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    io::{Error, ErrorKind},
+    path::Path,
+    sync::Arc,
+};
+
+use crate::core::message::{LegacyMessage, Message};
+
+use super::{
+    compression::Compression,
+    partition::PartitionConfig,
+    report::UpgradeReport,
+    segment::{
+        Segment, SegmentLimits, LOG_EXTENSION, OFFSET_INDEX_EXTENSION, SEGMENT_EXTENSIONS,
+        TIME_INDEX_EXTENSION,
+    },
+    vfs::VfsFile,
+};
+
+/// UPGRADE_DIR is the directory inside of a partition, where the upgraded segments are written
+/// before they replace the original ones.
+const UPGRADE_DIR: &str = "upgrade";
+
+/// UPGRADE_BATCH_SIZE is the amount of messages written to an upgraded segment at once.
+const UPGRADE_BATCH_SIZE: usize = 1000;
+
+/// LEGACY_NAME_LENGTH is the length of the names of the legacy segment files without
+/// the extension, which are the numbers of the segments.
+const LEGACY_NAME_LENGTH: usize = 8;
+
+/// LEGACY_HEADER_SIZE is the size of the header of a legacy record, which is the size
+/// of the message after it as a bincode-encoded u64.
+const LEGACY_HEADER_SIZE: usize = 8;
+
+/// Upgrades the segments of the partition in the given directory, which were written
+/// by the first version of the storage, to the current format and returns what was done.
+///
+/// The legacy segments are named by their numbers instead of the base offsets, and their logs
+/// have no file header and store every message as a separate record: a bincode-encoded u64
+/// size followed by the bincode-encoded LegacyMessage, without a checksum. Their indexes
+/// are deleted, since they are rebuilt from the log.
+///
+/// Every log is rewritten into a new segment, which then replaces the files with the base offset
+/// in the name, and only then the legacy files are deleted, so if the upgrade is interrupted,
+/// it is repeated on the next load. A record that could not be read is a torn write only
+/// in the last segment, then it is dropped along with the rest of its log. In the closed
+/// segments it fails the upgrade and nothing is changed in them.
+pub fn upgrade_partition(
+    config: &PartitionConfig,
+    path: &str,
+) -> Result<Vec<UpgradeReport>, Error> {
+    let segments = list_legacy_segments(config, path)?;
+    let Some(&last) = segments.keys().next_back() else {
+        return Ok(Vec::new());
+    };
+
+    let vfs = &config.vfs;
+    let upgrade_path = format!("{}/{}", path, UPGRADE_DIR);
+    let mut reports = Vec::with_capacity(segments.len());
+    let mut next_offset = None;
+
+    for (&number, extensions) in segments.iter() {
+        let log_path = legacy_file_path(path, number, LOG_EXTENSION);
+        if !extensions.contains(LOG_EXTENSION) {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("log `{}` is not found", log_path),
+            ));
+        }
+
+        let log = vfs.open(&log_path)?;
+        let (messages, dropped_bytes) = read_legacy_log(log.as_ref(), &log_path, number == last)?;
+        if let (Some(expected), Some(first)) = (next_offset, messages.first()) {
+            if first.offset != expected {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "log `{}` starts at offset {} instead of {}",
+                        log_path, first.offset, expected
+                    ),
+                ));
+            }
+        }
+
+        let records = messages.len();
+        if let Some(base_offset) = messages.first().map(|m| m.offset) {
+            next_offset = Some(base_offset + records);
+
+            remove_dir(config, &upgrade_path)?;
+            vfs.create_dir_all(&upgrade_path)?;
+            write_segment(config, &upgrade_path, base_offset, messages)?;
+            Segment::replace_files(vfs.as_ref(), &upgrade_path, path, base_offset)?;
+            config.sync_dir(path)?;
+        }
+
+        // the indexes are deleted before the log, the same as the files of a segment.
+        for extension in [OFFSET_INDEX_EXTENSION, TIME_INDEX_EXTENSION, LOG_EXTENSION] {
+            if extensions.contains(extension) {
+                vfs.remove_file(&legacy_file_path(path, number, extension))?;
+            }
+        }
+        config.sync_dir(path)?;
+
+        reports.push(UpgradeReport {
+            segment: log_path,
+            records,
+            dropped_bytes,
+        });
+    }
+
+    remove_dir(config, &upgrade_path)?;
+    Ok(reports)
+}
+
+// list_legacy_segments returns the extensions of the legacy segment files found
+// in the directory by the numbers of the segments.
+fn list_legacy_segments(
+    config: &PartitionConfig,
+    path: &str,
+) -> Result<BTreeMap<usize, HashSet<String>>, Error> {
+    let mut segments: BTreeMap<usize, HashSet<String>> = BTreeMap::new();
+
+    for name in config.vfs.read_dir(path)? {
+        let name = Path::new(&name);
+        let (Some(stem), Some(extension)) = (
+            name.file_stem().and_then(|s| s.to_str()),
+            name.extension().and_then(|e| e.to_str()),
+        ) else {
+            continue;
+        };
+        if stem.len() != LEGACY_NAME_LENGTH
+            || !stem.bytes().all(|b| b.is_ascii_digit())
+            || !SEGMENT_EXTENSIONS.contains(&extension)
+        {
+            continue;
+        }
+
+        let Ok(number) = stem.parse() else {
+            continue;
+        };
+        segments
+            .entry(number)
+            .or_default()
+            .insert(extension.to_string());
+    }
+
+    Ok(segments)
+}
+
+// read_legacy_log reads the messages of the legacy log and returns them along with the amount
+// of bytes left out after the last valid record, which could be only in the last segment.
+fn read_legacy_log(
+    log: &dyn VfsFile,
+    path: &str,
+    is_last: bool,
+) -> Result<(Vec<Message>, usize), Error> {
+    let mut data = vec![0u8; log.size()? as usize];
+    log.read_exact_at(&mut data, 0)?;
+
+    let mut messages: Vec<Message> = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let message = match decode_legacy_record(&data[position..]) {
+            Some((message, _))
+                if messages
+                    .last()
+                    .is_some_and(|m| m.offset + 1 != message.offset) =>
+            {
+                Err(format!(
+                    "record at {} has offset {} after {}",
+                    position,
+                    message.offset,
+                    messages.last().map_or(0, |m| m.offset)
+                ))
+            }
+            Some((message, size)) => Ok((message, size)),
+            None => Err(format!("record at {} could not be read", position)),
+        };
+
+        match message {
+            Ok((message, size)) => {
+                messages.push(message);
+                position += size;
+            }
+            Err(_) if is_last => break,
+            Err(e) => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("closed segment `{}` is corrupted: {}", path, e),
+                ))
+            }
+        }
+    }
+
+    Ok((messages, data.len() - position))
+}
+
+// decode_legacy_record decodes the legacy record at the beginning of the buffer and returns
+// the message along with the size of the record, or None if it is incomplete or invalid.
+fn decode_legacy_record(buffer: &[u8]) -> Option<(Message, usize)> {
+    let header = buffer.get(..LEGACY_HEADER_SIZE)?;
+    let size: u64 = bincode::deserialize(header).ok()?;
+    let end = LEGACY_HEADER_SIZE.checked_add(usize::try_from(size).ok()?)?;
+
+    let message: LegacyMessage = bincode::deserialize(buffer.get(LEGACY_HEADER_SIZE..end)?).ok()?;
+    Some((message.into(), end))
+}
+
+// write_segment writes the messages to a new segment with the given base offset
+// in the given directory and syncs it.
+fn write_segment(
+    config: &PartitionConfig,
+    path: &str,
+    base_offset: usize,
+    messages: Vec<Message>,
+) -> Result<(), Error> {
+    let mut segment = Segment::new(Arc::clone(&config.vfs), path.to_string(), base_offset)?;
+    let limits = SegmentLimits::unlimited();
+
+    let mut messages = messages.into_iter().peekable();
+    while messages.peek().is_some() {
+        let mut batch = messages.by_ref().take(UPGRADE_BATCH_SIZE).peekable();
+        segment.write_batch(&mut batch, &limits, Compression::None)?;
+    }
+    segment.sync()
+}
+
+fn legacy_file_path(path: &str, number: usize, extension: &str) -> String {
+    format!("{}/{:08}.{}", path, number, extension)
+}
+
+fn remove_dir(config: &PartitionConfig, path: &str) -> Result<(), Error> {
+    match config.vfs.remove_dir_all(path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
@@ -0,0 +1,141 @@
+use core::fmt;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::Error,
+    os::unix::fs::FileExt,
+    path::Path,
+    sync::Arc,
+};
+
+/// Vfs is the file system, on which the partitions keep their files.
+///
+/// RealVfs is the file system of the OS, and SimulatedVfs keeps the files in the memory
+/// and injects the faults on demand, so that the recovery of the storage could be tested.
+/// The paths are the strings, in which the directories are separated by `/`, and the errors
+/// are the same as the ones of std::fs, f.e. NotFound for a missing file.
+pub trait Vfs: fmt::Debug + Send + Sync {
+    /// Opens the file for reading and writing, creating it if it does not exist.
+    fn open(&self, path: &str) -> Result<Arc<dyn VfsFile>, Error>;
+
+    fn exists(&self, path: &str) -> bool;
+
+    /// Returns the names of the files in the directory, the nested directories are left out.
+    fn read_dir(&self, path: &str) -> Result<Vec<String>, Error>;
+
+    fn create_dir_all(&self, path: &str) -> Result<(), Error>;
+
+    fn remove_dir_all(&self, path: &str) -> Result<(), Error>;
+
+    fn remove_file(&self, path: &str) -> Result<(), Error>;
+
+    /// Moves the file, replacing the one that is at the destination.
+    fn rename(&self, from: &str, to: &str) -> Result<(), Error>;
+
+    /// Makes the changes of the entries of the directory durable.
+    fn sync_dir(&self, path: &str) -> Result<(), Error>;
+}
+
+/// VfsFile is a file opened by a Vfs.
+///
+/// It is read and written only at the given positions without a cursor,
+/// so a file could be shared and read concurrently.
+pub trait VfsFile: fmt::Debug + Send + Sync {
+    /// Fills the buffer with the bytes at the given position,
+    /// fails with UnexpectedEof if the file ends before that.
+    fn read_exact_at(&self, buffer: &mut [u8], position: u64) -> Result<(), Error>;
+
+    fn write_all_at(&self, data: &[u8], position: u64) -> Result<(), Error>;
+
+    /// Returns the size of the file in bytes.
+    fn size(&self) -> Result<u64, Error>;
+
+    fn set_len(&self, len: u64) -> Result<(), Error>;
+
+    /// Makes the content of the file durable.
+    fn sync_data(&self) -> Result<(), Error>;
+
+    /// Returns the file of the OS, if the file is on the real file system.
+    fn as_file(&self) -> Option<&File>;
+}
+
+/// RealVfs is the file system of the OS.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RealVfs;
+
+impl Vfs for RealVfs {
+    fn open(&self, path: &str) -> Result<Arc<dyn VfsFile>, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        Ok(Arc::new(file))
+    }
+
+    fn exists(&self, path: &str) -> bool {
+        Path::new(path).exists()
+    }
+
+    fn read_dir(&self, path: &str) -> Result<Vec<String>, Error> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry_path = entry?.path();
+            if !entry_path.is_file() {
+                continue;
+            }
+            if let Some(name) = entry_path.file_name().and_then(|n| n.to_str()) {
+                names.push(name.to_string());
+            }
+        }
+
+        Ok(names)
+    }
+
+    fn create_dir_all(&self, path: &str) -> Result<(), Error> {
+        fs::create_dir_all(path)
+    }
+
+    fn remove_dir_all(&self, path: &str) -> Result<(), Error> {
+        fs::remove_dir_all(path)
+    }
+
+    fn remove_file(&self, path: &str) -> Result<(), Error> {
+        fs::remove_file(path)
+    }
+
+    fn rename(&self, from: &str, to: &str) -> Result<(), Error> {
+        fs::rename(from, to)
+    }
+
+    fn sync_dir(&self, path: &str) -> Result<(), Error> {
+        File::open(path)?.sync_all()
+    }
+}
+
+impl VfsFile for File {
+    fn read_exact_at(&self, buffer: &mut [u8], position: u64) -> Result<(), Error> {
+        FileExt::read_exact_at(self, buffer, position)
+    }
+
+    fn write_all_at(&self, data: &[u8], position: u64) -> Result<(), Error> {
+        FileExt::write_all_at(self, data, position)
+    }
+
+    fn size(&self) -> Result<u64, Error> {
+        Ok(self.metadata()?.len())
+    }
+
+    fn set_len(&self, len: u64) -> Result<(), Error> {
+        File::set_len(self, len)
+    }
+
+    fn sync_data(&self) -> Result<(), Error> {
+        File::sync_data(self)
+    }
+
+    fn as_file(&self) -> Option<&File> {
+        Some(self)
+    }
+}